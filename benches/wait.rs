@@ -0,0 +1,100 @@
+//! Benchmarks for the overhead of spawning, waiting on, and reaping short
+//! lived children, both from one thread and from many threads at once, and of
+//! many threads timing out on the same cadence.
+
+#[macro_use]
+extern crate criterion;
+extern crate wait_timeout;
+
+use std::env;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::Criterion;
+use wait_timeout::ChildExt;
+
+const THREADS: u32 = 8;
+
+fn helper(name: &str, arg: &str) -> Command {
+    let mut me = env::current_exe().unwrap();
+    me.pop();
+    if me.ends_with("deps") {
+        me.pop();
+    }
+    me.push(name);
+    let mut cmd = Command::new(me);
+    cmd.arg(arg);
+    cmd
+}
+
+fn exit() -> Command {
+    helper("exit", "0")
+}
+
+fn spawn_and_wait(cmd: &mut Command) {
+    let mut child = cmd.spawn().unwrap();
+    let status = child.wait_timeout(Duration::from_secs(10)).unwrap();
+    assert!(status.unwrap().success());
+}
+
+fn single_thread(c: &mut Criterion) {
+    let mut cmd = exit();
+    c.bench_function("spawn_wait_reap", |b| b.iter(|| spawn_and_wait(&mut cmd)));
+}
+
+fn multi_thread(c: &mut Criterion) {
+    c.bench_function("spawn_wait_reap_8_threads", |b| {
+        b.iter_custom(|iters| {
+            let start = Instant::now();
+            let threads = (0..THREADS)
+                .map(|_| {
+                    thread::spawn(move || {
+                        let mut cmd = exit();
+                        for _ in 0..iters {
+                            spawn_and_wait(&mut cmd);
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            // report the time per wait rather than per round of waits
+            start.elapsed() / THREADS
+        })
+    });
+}
+
+fn multi_thread_timeouts(c: &mut Criterion) {
+    c.bench_function("timeout_1ms_8_threads", |b| {
+        b.iter_custom(|iters| {
+            let threads = (0..THREADS)
+                .map(|_| {
+                    thread::spawn(move || {
+                        let mut child = helper("sleep", "1000000").spawn().unwrap();
+                        let start = Instant::now();
+                        for _ in 0..iters {
+                            let status = child.wait_timeout(Duration::from_millis(1)).unwrap();
+                            assert!(status.is_none());
+                        }
+                        let elapsed = start.elapsed();
+                        child.kill().unwrap();
+                        child.wait().unwrap();
+                        elapsed
+                    })
+                })
+                .collect::<Vec<_>>();
+            // every thread waits concurrently, so report the average time a
+            // thread took, which is 1ms plus the overhead of each timeout
+            let total = threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .sum::<Duration>();
+            total / THREADS
+        })
+    });
+}
+
+criterion_group!(benches, single_thread, multi_thread, multi_thread_timeouts);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+//! Feeds arbitrary wait status words through the `ExitStatus` decoding and
+//! checks that the different views of a status agree with each other.
+
+#![no_main]
+
+use std::os::unix::process as unix;
+use std::process::ExitStatus;
+
+use libfuzzer_sys::fuzz_target;
+use wait_timeout::ExitStatusExt;
+
+fuzz_target!(|raw: i32| {
+    let status: ExitStatus = unix::ExitStatusExt::from_raw(raw);
+    let signal = unix::ExitStatusExt::signal(&status);
+
+    let states = [
+        status.exited(),
+        status.signaled(),
+        status.stopped(),
+        status.continued(),
+    ];
+    assert!(states.iter().filter(|&&s| s).count() <= 1, "{:#x}", raw);
+
+    assert_eq!(status.code().is_some(), status.exited(), "{:#x}", raw);
+    assert_eq!(signal.is_some(), status.signaled(), "{:#x}", raw);
+    assert_eq!(
+        status.stop_signal().is_some(),
+        status.stopped(),
+        "{:#x}",
+        raw
+    );
+    assert_eq!(status.raw_wait_status(), Some(raw));
+    if status.success() {
+        assert_eq!(status.code(), Some(0));
+    }
+
+    match signal {
+        Some(signal) => {
+            assert_eq!(status.signal_or(-1), signal);
+            assert_eq!(status.shell_code(), 128 + signal);
+        }
+        None => {
+            assert_eq!(status.signal_or(-1), -1);
+            assert_eq!(status.shell_code(), status.code_or(0));
+        }
+    }
+});
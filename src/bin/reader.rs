@@ -1,7 +1,6 @@
 use std::io::{stdin, Read};
 
-#[allow(unused_must_use)]
 fn main() {
     let mut buffer: [u8; 32] = Default::default();
-    stdin().read(&mut buffer);
+    let _ = stdin().read(&mut buffer);
 }
@@ -7,13 +7,36 @@
 //! current implementation registers a `SIGCHLD` handler and initializes some
 //! global state. This handler also works within multi-threaded environments.
 //! If your application is otherwise handling `SIGCHLD` then bugs may arise.
+//! On Linux 5.3 and later, and Android 12 (API level 31) and later, a pidfd is
+//! waited on instead, and no handler is registered unless a `Waiter` is
+//! created with `WaiterOptions::child_signal`. A wait then makes a fixed
+//! number of system calls and is only woken up by its own child, whereas with
+//! the handler every child exiting wakes up every wait, which then checks on
+//! each child being waited on.
+//!
+//! To help debugging, the implementation can be forced by setting the
+//! `RUST_WAIT_TIMEOUT_BACKEND` environment variable to `sigchld` or `pidfd`
+//! before the first wait. This is a debugging aid and not a stable interface:
+//! the accepted values may change in any release, and unsupported values are
+//! ignored, with a warning if the `log` feature is enabled.
+//!
+//! When the `log` feature is enabled the Unix implementation emits `trace!`,
+//! `debug!` and `warn!` events while waiting, which can help diagnose unexpected
+//! wakeups and timeouts. Nothing is ever logged from the signal handler itself.
+//!
+//! Every public function and method of this crate is safe to call. The
+//! `unsafe` code needed to talk to the operating system is kept internal, so
+//! the crate can be used from code which forbids `unsafe` itself. The `safe`
+//! module further narrows the API down to what never panics and never acts on
+//! a raw process identifier, descriptor or handle, for callers auditing their
+//! dependencies.
 //!
 //! # Example
 //!
 //! ```no_run
 //! use std::process::Command;
 //! use wait_timeout::ChildExt;
-//! use std::time::Duration;
+//! use std::time::{Duration, Instant};
 //!
 //! let mut child = Command::new("foo").spawn().unwrap();
 //!
@@ -33,10 +56,67 @@
 
 #[cfg(unix)]
 extern crate libc;
+#[cfg(feature = "log")]
+extern crate log;
 
-use std::io;
-use std::process::{Child, ExitStatus};
-use std::time::Duration;
+use std::cmp;
+use std::collections::TryReserveError;
+use std::io::{self, Write};
+use std::ops::{ControlFlow, Deref};
+#[cfg(windows)]
+use std::os::windows::io::RawHandle;
+#[cfg(unix)]
+use std::process::ChildStdout;
+use std::process::{Child, Command, ExitCode, ExitStatus, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Internal logging shims which forward to the `log` crate when the `log`
+// feature is enabled, and otherwise compile away entirely (the arguments are
+// still type checked so both configurations stay warning-free).
+#[cfg(feature = "log")]
+macro_rules! trace {
+    ($($t:tt)*) => (::log::trace!($($t)*))
+}
+#[cfg(feature = "log")]
+#[allow(unused_macros)]
+macro_rules! debug {
+    ($($t:tt)*) => (::log::debug!($($t)*))
+}
+#[cfg(feature = "log")]
+#[allow(unused_macros)]
+macro_rules! warn {
+    ($($t:tt)*) => (::log::warn!($($t)*))
+}
+#[cfg(not(feature = "log"))]
+macro_rules! trace {
+    ($($t:tt)*) => {
+        if false {
+            let _ = format_args!($($t)*);
+        }
+    };
+}
+#[cfg(not(feature = "log"))]
+#[allow(unused_macros)]
+macro_rules! debug {
+    ($($t:tt)*) => {
+        if false {
+            let _ = format_args!($($t)*);
+        }
+    };
+}
+#[cfg(not(feature = "log"))]
+#[allow(unused_macros)]
+macro_rules! warn {
+    ($($t:tt)*) => {
+        if false {
+            let _ = format_args!($($t)*);
+        }
+    };
+}
 
 #[cfg(unix)]
 #[path = "unix.rs"]
@@ -44,13 +124,18 @@ mod imp;
 #[cfg(windows)]
 #[path = "windows.rs"]
 mod imp;
+mod output;
+pub mod safe;
+
+pub use output::{Collected, OutputOptions, ReadError};
 
 /// Extension methods for the standard `std::process::Child` type.
 pub trait ChildExt {
     /// Deprecated, use `wait_timeout` instead.
     #[doc(hidden)]
     fn wait_timeout_ms(&mut self, ms: u32) -> io::Result<Option<ExitStatus>> {
-        self.wait_timeout(Duration::from_millis(ms as u64))
+        // Widened losslessly, the platform code handles the largest durations.
+        self.wait_timeout(Duration::from_millis(u64::from(ms)))
     }
 
     /// Wait for this child to exit, timing out after the duration `dur` has
@@ -58,13 +143,1719 @@ pub trait ChildExt {
     ///
     /// If `Ok(None)` is returned then the timeout period elapsed without the
     /// child exiting, and if `Ok(Some(..))` is returned then the child exited
-    /// with the specified exit code.
+    /// with the specified exit code. Once the child has exited, further calls
+    /// return the same status right away, as does `Child::wait`.
+    ///
+    /// `Ok(None)` is never returned before `dur` has elapsed. If the system
+    /// timer fires early the wait is simply resumed for the remaining time,
+    /// although it may of course still return some time after `dur`.
+    ///
+    /// On Unix, an error of kind `io::ErrorKind::NotFound` is returned if the
+    /// child was already reaped by something other than its `Child`, and its
+    /// status wasn't recorded through `Waiter::record_exit`.
     fn wait_timeout(&mut self, dur: Duration) -> io::Result<Option<ExitStatus>>;
+
+    /// Same as `wait_timeout`, but also reports whether the returned status
+    /// was reaped by this call or had already been reaped earlier.
+    ///
+    /// A status is reported as `Reaped::Cached` if a previous wait already
+    /// collected it, or if another thread's wait reaped the child on this
+    /// call's behalf. This can be used by accounting code which must not
+    /// count the same exit twice. Processes aren't reaped on Windows, so
+    /// there the status is always reported as `Reaped::Now`.
+    fn wait_timeout_reaped(&mut self, dur: Duration) -> io::Result<Option<(ExitStatus, Reaped)>>;
+
+    /// Same as `wait_timeout`, but also returns the moment at which the
+    /// child's exit was observed.
+    ///
+    /// The timestamp is captured as soon as the child is reaped, so it trails
+    /// the actual exit by the latency of the wakeup which noticed it. If the
+    /// child had already been reaped by an earlier wait then the timestamp is
+    /// the time of this call.
+    fn wait_timeout_timestamped(
+        &mut self,
+        dur: Duration,
+    ) -> io::Result<Option<(ExitStatus, Instant)>>;
+
+    /// Same as `wait_timeout`, but the wait can also be cut short from another
+    /// thread through `interrupt`.
+    ///
+    /// If the interrupt is set before the child exits then `Ok(None)` is
+    /// returned as if the timeout had elapsed, and `Interrupt::is_interrupted`
+    /// can be used to tell the two apart. Setting the interrupt wakes up the
+    /// wait immediately rather than waiting for the next loop iteration.
+    fn wait_timeout_interruptible(
+        &mut self,
+        dur: Duration,
+        interrupt: &Interrupt,
+    ) -> io::Result<Option<ExitStatus>>;
+
+    /// Same as `wait_timeout`, but also returns how many bytes are waiting to
+    /// be read from the child's stdout and stderr once the wait is over.
+    ///
+    /// Only pipes still held by the `Child` are counted, so this is 0 if they
+    /// weren't piped or were taken. After a timeout, output which is piling
+    /// up suggests that the child is still making progress, while empty pipes
+    /// suggest it may be hung. Nothing is read from the pipes.
+    fn wait_timeout_with_pending(
+        &mut self,
+        dur: Duration,
+    ) -> io::Result<(Option<ExitStatus>, usize)>;
+
+    /// Same as `wait_timeout`, but returns a `WaitResult` which has to be
+    /// inspected, so a timeout can't be mistaken for the child exiting.
+    fn wait_timeout_checked(&mut self, dur: Duration) -> io::Result<WaitResult> {
+        Ok(match self.wait_timeout(dur)? {
+            Some(status) => WaitResult::Exited(status),
+            None => WaitResult::TimedOut,
+        })
+    }
+
+    /// Same as `wait_timeout`, but retries the wait up to `max_transient`
+    /// times if it fails with a transient error, that is one of kind
+    /// `io::ErrorKind::Interrupted` or `io::ErrorKind::WouldBlock`.
+    ///
+    /// Interruptions by signals are always retried internally, so this is
+    /// only useful where setting up a wait can fail temporarily, for example
+    /// on a system that is briefly out of resources. Retries share the same
+    /// overall `dur`, and each is given only what is left of it. Any other
+    /// error, or the last transient error, is returned.
+    fn wait_timeout_retrying(
+        &mut self,
+        dur: Duration,
+        max_transient: usize,
+    ) -> io::Result<Option<ExitStatus>> {
+        let start = Instant::now();
+        let mut retries = 0;
+        loop {
+            let left = dur.saturating_sub(start.elapsed());
+            match self.wait_timeout(left) {
+                Err(ref e) if retries < max_transient && is_transient(e) => retries += 1,
+                ret => return ret,
+            }
+        }
+    }
+
+    /// Same as `wait_timeout`, but folds errors into the returned
+    /// `WaitTimeoutResult`, so every outcome can be handled in a single
+    /// `match`.
+    fn wait_timeout_result(&mut self, dur: Duration) -> WaitTimeoutResult {
+        match self.wait_timeout(dur) {
+            Ok(Some(status)) => WaitTimeoutResult::Exited(status),
+            Ok(None) => WaitTimeoutResult::TimedOut,
+            Err(e) => WaitTimeoutResult::Failed(e),
+        }
+    }
+
+    /// Same as `wait_timeout`, but returns `ControlFlow::Break` with the status
+    /// once the child has exited and `ControlFlow::Continue` if the timeout
+    /// elapsed first, which reads naturally in a loop that keeps waiting.
+    fn wait_timeout_flow(&mut self, dur: Duration) -> io::Result<ControlFlow<ExitStatus>> {
+        Ok(match self.wait_timeout(dur)? {
+            Some(status) => ControlFlow::Break(status),
+            None => ControlFlow::Continue(()),
+        })
+    }
+
+    /// Same as `wait_timeout`, but with the timeout specified in
+    /// milliseconds.
+    fn wait_timeout_millis(&mut self, ms: u64) -> io::Result<Option<ExitStatus>> {
+        self.wait_timeout(Duration::from_millis(ms))
+    }
+
+    /// Same as `wait_timeout`, but with the timeout specified in fractional
+    /// seconds.
+    ///
+    /// Negative and NaN values are treated as a zero timeout, and values too
+    /// large to be represented by a `Duration` are clamped to the largest
+    /// possible timeout.
+    fn wait_timeout_secs_f64(&mut self, secs: f64) -> io::Result<Option<ExitStatus>> {
+        let dur = if secs > 0.0 {
+            Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX)
+        } else {
+            Duration::from_secs(0)
+        };
+        self.wait_timeout(dur)
+    }
+
+    /// Same as `wait_timeout`, but calls `heartbeat` every `interval` while
+    /// the child is still running.
+    ///
+    /// This is meant for progress indicators and similar periodic checks which
+    /// shouldn't end the wait. If `heartbeat` returns `false` the wait is
+    /// abandoned and `Ok(None)` is returned, just as on a timeout. The
+    /// callback runs on the calling thread between blocking waits of at most
+    /// `interval` each, so `interval` should be nonzero.
+    fn wait_timeout_with_heartbeat<F>(
+        &mut self,
+        dur: Duration,
+        interval: Duration,
+        mut heartbeat: F,
+    ) -> io::Result<Option<ExitStatus>>
+    where
+        Self: Sized,
+        F: FnMut() -> bool,
+    {
+        let start = Instant::now();
+        loop {
+            let left = dur.saturating_sub(start.elapsed());
+            if let Some(status) = self.wait_timeout(cmp::min(left, interval))? {
+                return Ok(Some(status));
+            }
+            if start.elapsed() >= dur || !heartbeat() {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Check whether this child has exited without blocking, reporting whether
+    /// it's still running past `deadline`.
+    ///
+    /// This never blocks, and is intended for supervisors which run their own
+    /// loop rather than letting this crate own the blocking wait. A result of
+    /// `TryWaitResult::Overdue` means the child is still running and
+    /// `deadline` has passed, so the caller may want to kill it.
+    fn try_wait_deadline(&mut self, deadline: Instant) -> io::Result<TryWaitResult>;
+
+    /// Arrange for this child to be killed if it's still running after the
+    /// duration `dur` has elapsed.
+    ///
+    /// The returned guard borrows the child for its whole lifetime, and
+    /// dropping the guard before the duration elapses cancels the kill. If the
+    /// child has already exited no kill is scheduled at all. Otherwise the
+    /// child can only be reaped through `KillGuard::wait` and
+    /// `KillGuard::wait_timeout` while the guard is alive, and those stop the
+    /// timer before reaping, so the kill can't hit a recycled process
+    /// identifier. A child which isn't waited on through the guard should
+    /// still be waited on after the guard is dropped.
+    fn kill_after(&mut self, dur: Duration) -> io::Result<KillGuard<'_>>;
+
+    /// Kill the whole process group led by this child with `SIGKILL`.
+    ///
+    /// This requires the child to have been spawned as the leader of its own
+    /// process group, for example with `CommandExt::process_group_leader`, in
+    /// which case the group's identifier is the child's process identifier.
+    /// Any descendants which moved themselves into another process group or
+    /// session (for example daemons calling `setsid`) are not signaled.
+    ///
+    /// The group is signaled even if the child has already exited, as its
+    /// descendants may still be running in it, and a group which is already
+    /// empty isn't treated as an error. The identifier of a process group
+    /// isn't reused while the group has members. Note that the child itself is
+    /// not reaped and should still be waited on.
+    #[cfg(unix)]
+    fn kill_process_group(&mut self) -> io::Result<()>;
+
+    /// Same as `kill_after`, except the whole process group led by this child
+    /// is killed once the duration elapses.
+    ///
+    /// See `kill_process_group` for the requirements on how the child must be
+    /// spawned.
+    #[cfg(unix)]
+    fn kill_process_group_after(&mut self, dur: Duration) -> io::Result<KillGuard<'_>>;
+
+    /// Wait for this child to exit or for `stdout` to become readable,
+    /// whichever comes first, timing out after the duration `dur` has
+    /// elapsed.
+    ///
+    /// This is a building block for talking to a child over its pipes in a
+    /// single thread, noticing promptly if it dies instead of blocking on a
+    /// read forever. `stdout` is usually taken out of the child first, but
+    /// any of its pipes works. Unlike `wait_timeout`, the child's stdin is
+    /// left open. End of file counts as readable, and if the child exits
+    /// while output is pending, `Exited` is reported; the rest of the output
+    /// can still be read afterwards.
+    #[cfg(unix)]
+    fn wait_or_readable(&mut self, stdout: &ChildStdout, dur: Duration) -> io::Result<WaitOrRead>;
+
+    /// Wait for this child to exit or to write anything to its stdout or
+    /// stderr, whichever comes first, timing out after the duration `dur` has
+    /// elapsed.
+    ///
+    /// This is meant for health checks asking whether a child is alive and
+    /// responsive. Only the pipes still held by the `Child` are watched, and
+    /// nothing is read from them, so the output is left for the caller. As
+    /// with `wait_or_readable`, the child's stdin is left open, end of file
+    /// counts as output, and `Exited` takes precedence over pending output.
+    #[cfg(unix)]
+    fn wait_first_activity(&mut self, dur: Duration) -> io::Result<Activity>;
+
+    /// Wait like `wait_first_activity`, then return whatever the child wrote
+    /// to its stdout and stderr since the last call along with its status.
+    ///
+    /// Calling this in a loop pumps the child's output without blocking on
+    /// its pipes, for example to refresh a user interface every `dur`. Only
+    /// what can be read without blocking is read, and a pipe is dropped from
+    /// the `Child` once it reaches end of file. Once the child has exited, its
+    /// status is returned, but output still left in its pipes can be read by
+    /// calling this again until they're closed.
+    #[cfg(unix)]
+    fn poll_snapshot(&mut self, dur: Duration) -> io::Result<WaitSnapshot>;
 }
 
 impl ChildExt for Child {
     fn wait_timeout(&mut self, dur: Duration) -> io::Result<Option<ExitStatus>> {
+        Ok(self.wait_timeout_reaped(dur)?.map(|(status, _)| status))
+    }
+
+    fn wait_timeout_reaped(&mut self, dur: Duration) -> io::Result<Option<(ExitStatus, Reaped)>> {
+        drop(self.stdin.take());
+        let ret = imp::wait_timeout(self, dur, None)?;
+        Ok(ret.map(|exit| (exit.status, exit.reaped)))
+    }
+
+    fn wait_timeout_timestamped(
+        &mut self,
+        dur: Duration,
+    ) -> io::Result<Option<(ExitStatus, Instant)>> {
+        drop(self.stdin.take());
+        let ret = imp::wait_timeout(self, dur, None)?;
+        Ok(ret.map(|exit| (exit.status, exit.at)))
+    }
+
+    fn wait_timeout_interruptible(
+        &mut self,
+        dur: Duration,
+        interrupt: &Interrupt,
+    ) -> io::Result<Option<ExitStatus>> {
         drop(self.stdin.take());
-        imp::wait_timeout(self, dur)
+        let ret = imp::wait_timeout(self, dur, Some(&interrupt.inner))?;
+        Ok(ret.map(|exit| exit.status))
+    }
+
+    fn wait_timeout_with_pending(
+        &mut self,
+        dur: Duration,
+    ) -> io::Result<(Option<ExitStatus>, usize)> {
+        let status = self.wait_timeout(dur)?;
+        Ok((status, imp::pending_bytes(self)?))
+    }
+
+    fn try_wait_deadline(&mut self, deadline: Instant) -> io::Result<TryWaitResult> {
+        Ok(match self.try_wait()? {
+            Some(status) => TryWaitResult::Exited(status),
+            None if Instant::now() >= deadline => TryWaitResult::Overdue,
+            None => TryWaitResult::Running,
+        })
+    }
+
+    fn kill_after(&mut self, dur: Duration) -> io::Result<KillGuard<'_>> {
+        let killer = imp::Killer::new(self);
+        let deadline = match self.try_wait()? {
+            Some(_) => None,
+            None => Instant::now().checked_add(dur),
+        };
+        KillGuard::new(self, killer, deadline)
+    }
+
+    #[cfg(unix)]
+    fn kill_process_group(&mut self) -> io::Result<()> {
+        imp::Killer::process_group(self).kill_checked()
+    }
+
+    #[cfg(unix)]
+    fn kill_process_group_after(&mut self, dur: Duration) -> io::Result<KillGuard<'_>> {
+        // Unlike `kill_after` the timer is started even if the child has
+        // exited, as the rest of its group may still be running.
+        let killer = imp::Killer::process_group(self);
+        let deadline = Instant::now().checked_add(dur);
+        KillGuard::new(self, killer, deadline)
+    }
+
+    #[cfg(unix)]
+    fn wait_or_readable(&mut self, stdout: &ChildStdout, dur: Duration) -> io::Result<WaitOrRead> {
+        use std::os::unix::io::AsRawFd;
+
+        let fd = stdout.as_raw_fd();
+        Ok(match imp::wait_or_readable(self, [fd, -1], dur)? {
+            Some(exit) => WaitOrRead::Exited(exit.status),
+            None if imp::readable(fd)? => WaitOrRead::Readable,
+            None => WaitOrRead::TimedOut,
+        })
+    }
+
+    #[cfg(unix)]
+    fn wait_first_activity(&mut self, dur: Duration) -> io::Result<Activity> {
+        use std::os::unix::io::AsRawFd;
+
+        let stdout = self.stdout.as_ref().map_or(-1, |p| p.as_raw_fd());
+        let stderr = self.stderr.as_ref().map_or(-1, |p| p.as_raw_fd());
+        if let Some(exit) = imp::wait_or_readable(self, [stdout, stderr], dur)? {
+            return Ok(Activity::Exited(exit.status));
+        }
+        for &fd in [stdout, stderr].iter() {
+            if fd >= 0 && imp::readable(fd)? {
+                return Ok(Activity::Output);
+            }
+        }
+        Ok(Activity::TimedOut)
+    }
+
+    #[cfg(unix)]
+    fn poll_snapshot(&mut self, dur: Duration) -> io::Result<WaitSnapshot> {
+        let status = match self.wait_first_activity(dur)? {
+            Activity::Exited(status) => Some(status),
+            Activity::Output | Activity::TimedOut => None,
+        };
+        let mut snapshot = WaitSnapshot {
+            status,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        };
+        if read_available(self.stdout.as_mut(), &mut snapshot.stdout)? {
+            self.stdout = None;
+        }
+        if read_available(self.stderr.as_mut(), &mut snapshot.stderr)? {
+            self.stderr = None;
+        }
+        Ok(snapshot)
+    }
+}
+
+// Reads what can be read from `pipe` without blocking into `buf`, returning
+// whether it reached end of file.
+#[cfg(unix)]
+fn read_available<R>(pipe: Option<&mut R>, buf: &mut Vec<u8>) -> io::Result<bool>
+where
+    R: std::io::Read + std::os::unix::io::AsRawFd,
+{
+    let pipe = match pipe {
+        Some(pipe) => pipe,
+        None => return Ok(false),
+    };
+    let fd = pipe.as_raw_fd();
+    if !imp::readable(fd)? {
+        return Ok(false);
+    }
+    // A readable pipe with nothing in it has been closed.
+    let n = imp::available(fd)?;
+    if n == 0 {
+        return Ok(true);
+    }
+    let start = buf.len();
+    buf.resize(start + n, 0);
+    pipe.read_exact(&mut buf[start..])?;
+    Ok(false)
+}
+
+/// The result of `ChildExt::wait_or_readable`.
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitOrRead {
+    /// The child has exited with the specified status.
+    Exited(ExitStatus),
+    /// The child is still running, and reading from its pipe won't block.
+    Readable,
+    /// The child is still running and nothing could be read before the
+    /// timeout elapsed.
+    TimedOut,
+}
+
+/// The result of `ChildExt::wait_first_activity`.
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    /// The child has exited with the specified status.
+    Exited(ExitStatus),
+    /// The child is still running, and reading from its stdout or stderr
+    /// won't block.
+    Output,
+    /// The child is still running and wrote nothing before the timeout
+    /// elapsed.
+    TimedOut,
+}
+
+/// The result of `ChildExt::poll_snapshot`.
+#[cfg(unix)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaitSnapshot {
+    /// The status of the child, if it has exited.
+    pub status: Option<ExitStatus>,
+    /// What the child wrote to its stdout since the last snapshot.
+    pub stdout: Vec<u8>,
+    /// What the child wrote to its stderr since the last snapshot.
+    pub stderr: Vec<u8>,
+}
+
+#[cfg(unix)]
+impl WaitSnapshot {
+    /// Returns whether the child was still running when the snapshot was
+    /// taken.
+    pub fn is_running(&self) -> bool {
+        self.status.is_none()
+    }
+}
+
+/// The result of `ChildExt::wait_timeout_checked`.
+#[must_use = "the child may not have exited if the wait timed out"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitResult {
+    /// The child has exited with the specified status.
+    Exited(ExitStatus),
+    /// The timeout elapsed before the child exited.
+    TimedOut,
+}
+
+impl WaitResult {
+    /// Returns the status if the child exited, as returned by
+    /// `ChildExt::wait_timeout`.
+    pub fn into_option(self) -> Option<ExitStatus> {
+        match self {
+            WaitResult::Exited(status) => Some(status),
+            WaitResult::TimedOut => None,
+        }
+    }
+}
+
+impl From<WaitResult> for Option<ExitStatus> {
+    fn from(result: WaitResult) -> Option<ExitStatus> {
+        result.into_option()
+    }
+}
+
+/// The result of `ChildExt::wait_timeout_result`.
+#[must_use = "the wait may have timed out or failed"]
+#[derive(Debug)]
+pub enum WaitTimeoutResult {
+    /// The child has exited with the specified status.
+    Exited(ExitStatus),
+    /// The timeout elapsed before the child exited.
+    TimedOut,
+    /// Waiting on the child failed.
+    Failed(io::Error),
+}
+
+impl WaitTimeoutResult {
+    /// Converts back into the result returned by `ChildExt::wait_timeout`.
+    pub fn into_result(self) -> io::Result<Option<ExitStatus>> {
+        match self {
+            WaitTimeoutResult::Exited(status) => Ok(Some(status)),
+            WaitTimeoutResult::TimedOut => Ok(None),
+            WaitTimeoutResult::Failed(e) => Err(e),
+        }
+    }
+}
+
+/// The result of `ChildExt::try_wait_deadline`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryWaitResult {
+    /// The child has exited with the specified status.
+    Exited(ExitStatus),
+    /// The child is still running and the deadline hasn't passed yet.
+    Running,
+    /// The child is still running past the deadline.
+    Overdue,
+}
+
+/// A flag which cancels waits from another thread.
+///
+/// An interrupt is passed to `ChildExt::wait_timeout_interruptible` or
+/// `CommandExt::wait_with_output_interruptible`, and once `interrupt` is
+/// called every wait watching it, current or future, returns early. Clones of
+/// an interrupt all refer to the same underlying flag, which can't be cleared
+/// once set.
+#[derive(Clone)]
+pub struct Interrupt {
+    inner: Arc<imp::Interrupt>,
+    // Callbacks run once this is set, for waits which can't watch the
+    // platform's primitive directly, keyed by an identifier to remove them.
+    wakers: Arc<Mutex<Vec<(usize, Waker)>>>,
+}
+
+type Waker = Box<dyn Fn() + Send>;
+
+impl Interrupt {
+    /// Creates a new interrupt which hasn't been set yet.
+    pub fn new() -> io::Result<Interrupt> {
+        Ok(Interrupt {
+            inner: Arc::new(imp::Interrupt::new()?),
+            wakers: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /// Sets this interrupt, waking up all waits watching it.
+    pub fn interrupt(&self) {
+        self.inner.interrupt();
+        for (_, waker) in self.lock_wakers().iter() {
+            waker();
+        }
+    }
+
+    /// Returns whether this interrupt has been set.
+    pub fn is_interrupted(&self) -> bool {
+        self.inner.is_interrupted()
+    }
+
+    // Runs `waker` when this interrupt is set, until the returned guard is
+    // dropped. If it's already set the waker runs right away.
+    fn on_interrupt(&self, waker: Waker) -> WakerGuard<'_> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let mut wakers = self.lock_wakers();
+        wakers.push((id, waker));
+        if self.is_interrupted() {
+            (wakers.last().unwrap().1)();
+        }
+        WakerGuard {
+            interrupt: self,
+            id,
+        }
+    }
+
+    fn lock_wakers(&self) -> MutexGuard<'_, Vec<(usize, Waker)>> {
+        self.wakers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+struct WakerGuard<'a> {
+    interrupt: &'a Interrupt,
+    id: usize,
+}
+
+impl<'a> Drop for WakerGuard<'a> {
+    fn drop(&mut self) {
+        self.interrupt
+            .lock_wakers()
+            .retain(|&(id, _)| id != self.id);
+    }
+}
+
+/// Options used to configure the process-wide state behind a `Waiter`.
+///
+/// The state used to wait on children is shared by the whole process, so these
+/// options only take effect if they're used to create the first `Waiter`, and
+/// before any call to `ChildExt::wait_timeout`, which implicitly uses the
+/// default options. The exceptions are `spin`, `around_blocking`,
+/// `max_pending` and `block_signals`, which only configure the `Waiter`
+/// created with these options.
+#[cfg(unix)]
+#[derive(Clone, Debug, Default)]
+pub struct WaiterOptions {
+    inner: imp::Options,
+    spin: Duration,
+    blocking: Option<Blocking>,
+    max_pending: Option<usize>,
+    block_signals: Vec<i32>,
+}
+
+// The hooks configured through `WaiterOptions::around_blocking`.
+#[cfg(unix)]
+#[derive(Clone)]
+struct Blocking {
+    before: Arc<dyn Fn() + Send + Sync>,
+    after: Arc<dyn Fn() + Send + Sync>,
+}
+
+#[cfg(unix)]
+impl std::fmt::Debug for Blocking {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Blocking").finish_non_exhaustive()
+    }
+}
+
+#[cfg(unix)]
+impl WaiterOptions {
+    /// Creates a new set of options with the default configuration.
+    pub fn new() -> WaiterOptions {
+        WaiterOptions::default()
+    }
+
+    /// Configures the signal which is delivered when a child exits, which
+    /// defaults to `SIGCHLD`.
+    ///
+    /// This is only useful in environments, such as some sandboxes, which
+    /// intercept `SIGCHLD` and deliver another signal instead. It's up to the
+    /// caller to ensure that the chosen signal is actually raised whenever a
+    /// child exits, otherwise waits will only ever end by timing out. Setting
+    /// it makes the waiter install the signal handler even where pidfds are
+    /// used, which otherwise need no handler at all.
+    ///
+    /// All waiters in a process must agree on this signal, since only a
+    /// single handler is ever installed. Creating a waiter with a signal other
+    /// than the one the handler was installed for returns an error, as does a
+    /// signal which can't be handled, such as `SIGKILL`, in both cases of kind
+    /// `io::ErrorKind::InvalidInput`.
+    pub fn child_signal(&mut self, signal: i32) -> &mut WaiterOptions {
+        self.inner.child_signal = Some(signal);
+        self
+    }
+
+    /// Configures whether the signal handler is installed with `SA_RESTART`,
+    /// which defaults to `true`.
+    ///
+    /// With `SA_RESTART` most blocking system calls interrupted by a child
+    /// exiting are transparently restarted by the kernel, which keeps host code
+    /// that doesn't handle `EINTR` working. Some calls, such as `poll` and
+    /// `select`, are never restarted regardless.
+    ///
+    /// Disabling it makes every blocking call interrupted by a child exiting
+    /// fail with `EINTR`, consistently. This suits hosts which rely on seeing
+    /// `EINTR` to notice signals, but all of their blocking calls must then be
+    /// prepared to retry. The standard library already retries on `EINTR` in
+    /// most places, and the waits in this crate always do.
+    pub fn restart(&mut self, restart: bool) -> &mut WaiterOptions {
+        self.inner.restart = restart;
+        self
+    }
+
+    /// Configures how long `Waiter::wait_timeout` busy-polls the child before
+    /// blocking, which defaults to zero.
+    ///
+    /// Setting up a blocking wait costs a few system calls, which dominates
+    /// for children that usually exit within microseconds. While spinning the
+    /// child is checked with `try_wait` in a loop which yields to other
+    /// threads, so this burns CPU time and is only worthwhile for very short
+    /// durations. A wait with a timeout no longer than `spin` never sets up a
+    /// blocking wait at all, which makes very short timeouts cheap.
+    pub fn spin(&mut self, spin: Duration) -> &mut WaiterOptions {
+        self.spin = spin;
+        self
+    }
+
+    /// Limits how many waits can be in progress on the `Waiter` at once, which
+    /// is unlimited by default.
+    ///
+    /// Once `max` calls to `Waiter::wait_timeout` are in progress, further
+    /// calls fail immediately rather than adding to the bookkeeping. This
+    /// surfaces runaway spawning early instead of letting memory grow without
+    /// bound. Use `Waiter::pending_count` to monitor how close to the limit a
+    /// waiter is.
+    pub fn max_pending(&mut self, max: usize) -> &mut WaiterOptions {
+        self.max_pending = Some(max);
+        self
+    }
+
+    /// Configures hooks which are called right before the `Waiter` blocks,
+    /// and right after it's done blocking.
+    ///
+    /// This is meant for language bindings, which can use it to release the
+    /// host runtime's global lock for the duration of the wait and acquire it
+    /// again afterwards. `after` is called exactly once for every call to
+    /// `before`, even if the wait fails or panics, and both are called on the
+    /// waiting thread. Neither is called by waits which don't block, such as
+    /// `drain_exits`, or when a spinning wait sees the child exit.
+    pub fn around_blocking<B, A>(&mut self, before: B, after: A) -> &mut WaiterOptions
+    where
+        B: Fn() + Send + Sync + 'static,
+        A: Fn() + Send + Sync + 'static,
+    {
+        self.blocking = Some(Blocking {
+            before: Arc::new(before),
+            after: Arc::new(after),
+        });
+        self
+    }
+
+    /// Configures signals which are blocked on the waiting thread while the
+    /// `Waiter` blocks, none by default.
+    ///
+    /// In a process which receives many signals, each one delivered to the
+    /// waiting thread interrupts the wait, which then has to start over.
+    /// Blocking them avoids those wakeups: they stay pending and are delivered
+    /// once the wait is over, when the thread's previous signal mask is
+    /// restored. Other threads are unaffected. The signal delivered when a
+    /// child exits is never blocked, even if listed, and neither are `SIGKILL`
+    /// and `SIGSTOP`, which can't be. Creating a waiter with an invalid signal
+    /// returns an error.
+    pub fn block_signals(&mut self, signals: &[i32]) -> &mut WaiterOptions {
+        self.block_signals = signals.to_vec();
+        self
+    }
+}
+
+/// A handle to the process-wide state used to wait on children on Unix.
+///
+/// Waiting with a timeout on Unix requires installing a signal handler and
+/// some global state, which `ChildExt::wait_timeout` sets up implicitly. A
+/// `Waiter` allows configuring that state through `WaiterOptions` before it's
+/// first used.
+///
+/// A waiter is `Send` and `Sync`, and all per-wait state lives on the stack of
+/// the waiting thread, so a single waiter can be shared behind an `Arc` and
+/// used to wait on different children from many threads concurrently.
+///
+/// A waiter can also watch a set of children for an event loop: its file
+/// descriptor becomes readable when a child exits, after which `drain_exits`
+/// reports which of the watched children have exited. Where pidfds are used,
+/// and no `WaiterOptions::child_signal` is configured, the descriptor is an
+/// epoll instance which only becomes readable for watched children, and no
+/// signal handler is installed. Otherwise it's notified by the signal handler
+/// of every child exiting, and at most 64 such waiters can be alive at once.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Waiter {
+    inner: imp::Waiter,
+    spin: Duration,
+    blocking: Option<Blocking>,
+    max_pending: Option<usize>,
+    pending: AtomicUsize,
+    mask: Option<imp::SignalMask>,
+}
+
+#[cfg(unix)]
+impl Waiter {
+    /// Creates a new waiter, initializing the process-wide state with the
+    /// default options if it hasn't been already.
+    ///
+    /// Where the signal handler is used, an error of kind
+    /// `io::ErrorKind::Other` is returned if 64 waiters are already alive, see
+    /// the type's docs.
+    pub fn new() -> io::Result<Waiter> {
+        Waiter::new_with_capacity(0)
+    }
+
+    /// Same as `new`, but preallocates room for `cap` children being waited on
+    /// concurrently, and for `cap` watched children.
+    ///
+    /// This avoids repeatedly growing the bookkeeping during a burst of spawns
+    /// when the number of children is known up front.
+    pub fn new_with_capacity(cap: usize) -> io::Result<Waiter> {
+        let waiter = Waiter::with_capacity(&WaiterOptions::new(), cap)?;
+        imp::reserve(cap)?;
+        Ok(waiter)
+    }
+
+    /// Creates a new waiter, initializing the process-wide state with
+    /// `options` if it hasn't been already.
+    pub fn with_options(options: &WaiterOptions) -> io::Result<Waiter> {
+        Waiter::with_capacity(options, 0)
+    }
+
+    fn with_capacity(options: &WaiterOptions, cap: usize) -> io::Result<Waiter> {
+        let inner = imp::Waiter::new(cap, &options.inner)?;
+        let mask = if options.block_signals.is_empty() {
+            None
+        } else {
+            Some(imp::SignalMask::new(&options.block_signals)?)
+        };
+        Ok(Waiter {
+            inner,
+            spin: options.spin,
+            blocking: options.blocking.clone(),
+            max_pending: options.max_pending,
+            pending: AtomicUsize::new(0),
+            mask,
+        })
+    }
+
+    /// Creates a new handle to the same process-wide state, for example to
+    /// hand to a worker thread instead of sharing this waiter behind an `Arc`.
+    ///
+    /// All waiters share the state used to reap children, so a clone waits on
+    /// children exactly like this waiter does, and is configured with the same
+    /// options. It gets its own file descriptor and set of watched children
+    /// though, which start out empty, and its own count of pending waits.
+    /// Where the signal handler is used it counts against the limit of 64 live
+    /// waiters, and an error of kind `io::ErrorKind::Other` is returned once
+    /// that's reached.
+    pub fn try_clone(&self) -> io::Result<Waiter> {
+        Ok(Waiter {
+            inner: self.inner.try_clone()?,
+            spin: self.spin,
+            blocking: self.blocking.clone(),
+            max_pending: self.max_pending,
+            pending: AtomicUsize::new(0),
+            mask: self.mask,
+        })
+    }
+
+    /// Returns how many calls to `wait_timeout` are currently in progress on
+    /// this waiter.
+    pub fn pending_count(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    // Counts a wait as pending until the returned guard is dropped, failing if
+    // that would exceed `max_pending`.
+    fn pending(&self) -> io::Result<Pending<'_>> {
+        let prev = self.pending.fetch_add(1, Ordering::SeqCst);
+        let pending = Pending(&self.pending);
+        match self.max_pending {
+            Some(max) if prev >= max => Err(io::Error::other(format!(
+                "already waiting on {} children with this `Waiter`",
+                max
+            ))),
+            _ => Ok(pending),
+        }
+    }
+
+    // Runs `f`, which may block, between the hooks of `around_blocking` and
+    // with the signals of `block_signals` blocked.
+    fn blocking<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        struct After<'a>(&'a Blocking);
+        impl<'a> Drop for After<'a> {
+            fn drop(&mut self) {
+                (self.0.after)();
+            }
+        }
+        let _after = self.blocking.as_ref().map(|b| {
+            (b.before)();
+            After(b)
+        });
+        let _mask = self.mask.as_ref().map(|m| m.block());
+        f()
+    }
+
+    /// Wait for `child` to exit, timing out after the duration `dur` has
+    /// elapsed.
+    ///
+    /// This has the same semantics as `ChildExt::wait_timeout`.
+    pub fn wait_timeout(&self, child: &mut Child, dur: Duration) -> io::Result<Option<ExitStatus>> {
+        let _pending = self.pending()?;
+        drop(child.stdin.take());
+        let start = Instant::now();
+        let spin = cmp::min(self.spin, dur);
+        while start.elapsed() < spin {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(status));
+            }
+            thread::yield_now();
+        }
+        let dur = dur.saturating_sub(start.elapsed());
+        let exit = self.blocking(|| imp::wait_timeout(child, dur, None))?;
+        Ok(exit.map(|exit| exit.status))
+    }
+
+    /// Start watching `child`, so that its exit is reported by `drain_exits`.
+    ///
+    /// A child which has already exited is reported by the very next call to
+    /// `drain_exits`, even though no notification for it will arrive.
+    pub fn watch(&self, child: &Child) {
+        self.inner.watch(child, 0)
+    }
+
+    /// Same as `watch`, but with a `priority` deciding where the child's exit
+    /// is listed by `drain_exits` and `poll_all`.
+    ///
+    /// Children which exit by the same call are returned highest priority
+    /// first, so urgent children can be handled before the rest. Children
+    /// watched with `watch` have priority 0, and the order among children of
+    /// equal priority is unspecified. Watching a child again changes its
+    /// priority.
+    pub fn watch_with_priority(&self, child: &Child, priority: i32) {
+        self.inner.watch(child, priority)
+    }
+
+    /// Returns whether the child `pid` is watched by this waiter, which is
+    /// the case until its exit has been reported by `drain_exits`.
+    pub fn contains(&self, pid: u32) -> bool {
+        self.inner.contains(pid)
+    }
+
+    /// Stops watching the child `pid`, so its exit won't be reported by
+    /// `drain_exits`.
+    ///
+    /// The child itself is left alone, and can still be waited on through its
+    /// `Child` as usual. Where the signal handler is used its exit still makes
+    /// this waiter's file descriptor readable, like that of any other child,
+    /// but is otherwise ignored. An error of kind `io::ErrorKind::NotFound` is
+    /// returned if the child isn't watched.
+    pub fn remove(&self, pid: u32) -> io::Result<()> {
+        self.inner.remove(pid)
+    }
+
+    /// Returns the status of `child` if it has exited, without reaping it and
+    /// without blocking.
+    ///
+    /// The status is read with `waitid` and `WNOWAIT`, so the child stays a
+    /// zombie and its `Child` can still be waited on as usual afterwards. The
+    /// child doesn't need to be watched by this waiter.
+    ///
+    /// An error of kind `io::ErrorKind::NotFound` is returned if the child was
+    /// already reaped.
+    pub fn peek(&self, child: &Child) -> io::Result<Option<ExitStatus>> {
+        imp::peek(child.id()).map_err(|e| imp::not_our_child(e, child.id()))
+    }
+
+    /// Returns the identifier and status of every watched child which has
+    /// exited since the last call, without blocking.
+    ///
+    /// This also drains this waiter's file descriptor, so with an
+    /// edge-triggered event loop it should be called each time the descriptor
+    /// becomes readable. An empty list is returned if nothing has exited.
+    ///
+    /// Children are not reaped by this, so their `Child` can still be waited
+    /// on as usual to collect the status. A child is reported only once, after
+    /// which it's no longer watched, and a child whose status is collected
+    /// through its `Child` before it's harvested here is never reported.
+    pub fn drain_exits(&self) -> io::Result<Vec<(u32, ExitStatus)>> {
+        self.inner.drain_exits()
+    }
+
+    /// Same as `drain_exits`, but blocks for up to `dur` until at least one
+    /// watched child has exited.
+    ///
+    /// Every watched child which exited by the time this wakes up is returned
+    /// at once, which lets a single thread supervise many children. An empty
+    /// list is returned if the timeout elapses first.
+    pub fn poll_all(&self, dur: Duration) -> io::Result<Vec<(u32, ExitStatus)>> {
+        self.blocking(|| self.inner.poll_all(dur))
+    }
+
+    /// Reaps every child of this process which has exited but isn't watched
+    /// by this waiter, and for which `known` returns `false`, returning their
+    /// identifiers and statuses.
+    ///
+    /// This is meant for a process acting as a subreaper through
+    /// `prctl(PR_SET_CHILD_SUBREAPER)`, such as a container's init. Orphaned
+    /// descendants are then reparented to it, and since nothing holds a
+    /// `Child` for them they would otherwise remain zombies. Where the signal
+    /// handler is used, their exits trigger the same notifications as any
+    /// other child, so this waiter's file descriptor becomes readable when one
+    /// of them needs reaping. Where pidfds are used, orphans can't be watched
+    /// ahead of time, so this should be called periodically instead.
+    ///
+    /// `known` is given the identifier of each other child and must return
+    /// `true` for every child this process still expects to wait on through
+    /// its `Child`, as those would otherwise be reaped here and waiting on them
+    /// would fail. Children are found through `/proc`, so this is only
+    /// available on Linux and Android.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn reap_orphans<F>(&self, mut known: F) -> io::Result<Vec<(u32, ExitStatus)>>
+    where
+        F: FnMut(u32) -> bool,
+    {
+        self.inner.reap_orphans(&mut known)
+    }
+
+    /// Records the status of the child `pid`, as reaped by a reaper outside of
+    /// this crate, such as a runtime looping on `waitpid(-1, ..)`.
+    ///
+    /// Waits on a child which was reaped elsewhere can't collect its status
+    /// themselves, so they pick up the one recorded here instead, reported as
+    /// `Reaped::Cached`. `raw_status` is the status word as filled in by
+    /// `waitpid`. Each recorded status is handed out to a single wait, after
+    /// which waiting on that child again fails as it would without this.
+    pub fn record_exit(&self, pid: u32, raw_status: i32) {
+        use std::os::unix::process::ExitStatusExt;
+
+        imp::record_exit(pid, ExitStatus::from_raw(raw_status))
+    }
+}
+
+#[cfg(unix)]
+struct Pending<'a>(&'a AtomicUsize);
+
+#[cfg(unix)]
+impl<'a> Drop for Pending<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(unix)]
+impl std::os::unix::io::AsRawFd for Waiter {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+// Everything the platform implementations learn about a child's exit.
+struct Exit {
+    status: ExitStatus,
+    reaped: Reaped,
+    at: Instant,
+}
+
+impl Exit {
+    fn new(status: ExitStatus, reaped: Reaped) -> Exit {
+        Exit {
+            status,
+            reaped,
+            at: Instant::now(),
+        }
+    }
+}
+
+/// Describes how an exit status returned by `ChildExt::wait_timeout_reaped`
+/// was obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reaped {
+    /// The child was reaped by this call.
+    Now,
+    /// The child had already been reaped, and a previously collected status
+    /// was returned.
+    Cached,
+}
+
+/// Extension methods for the standard `std::process::ExitStatus` type.
+///
+/// These decode the status with the platform's `WIF*` macros on Unix. On
+/// Windows a process can only ever exit, so `exited` is always `true` and the
+/// other predicates are always `false`.
+///
+/// Note that some of these methods share their name with methods of
+/// `std::os::unix::process::ExitStatusExt`, so the two traits shouldn't be
+/// imported into the same scope.
+pub trait ExitStatusExt {
+    /// Returns whether the process exited normally, as with `WIFEXITED`.
+    fn exited(&self) -> bool;
+
+    /// Returns whether the process was terminated by a signal, as with
+    /// `WIFSIGNALED`.
+    fn signaled(&self) -> bool;
+
+    /// Returns whether the process was stopped by a signal, as with
+    /// `WIFSTOPPED`.
+    fn stopped(&self) -> bool;
+
+    /// Returns whether the process was resumed by `SIGCONT`, as with
+    /// `WIFCONTINUED`.
+    fn continued(&self) -> bool;
+
+    /// Returns the signal which stopped the process, as with `WSTOPSIG`, if
+    /// the process was stopped.
+    fn stop_signal(&self) -> Option<i32>;
+
+    /// Returns the status word exactly as reported by `waitpid` on Unix, and
+    /// `None` on Windows.
+    ///
+    /// The word is never rewritten, so it can be passed straight to `libc`'s
+    /// `WIF*` macros. On Linux, for example, the low 7 bits hold the
+    /// terminating signal (or `0x7f` if stopped), bit 7 is set if a core was
+    /// dumped, and bits 8 to 15 hold the exit code or stop signal. Unlike a
+    /// plain exit code it must not be compared with integers directly.
+    fn raw_wait_status(&self) -> Option<i32>;
+
+    /// Returns the exit code of the process, or `default` if it was
+    /// terminated by a signal.
+    fn code_or(&self, default: i32) -> i32;
+
+    /// Returns whether the process exited with one of `ok_codes`, for
+    /// programs such as `diff` which exit with a nonzero code on success. A
+    /// process terminated by a signal never counts as a success.
+    fn is_success_code(&self, ok_codes: &[i32]) -> bool;
+
+    /// Returns the signal which terminated the process, if any, as with
+    /// `std::os::unix::process::ExitStatusExt::signal`. This is always `None`
+    /// on Windows, which makes it usable in cross-platform code.
+    fn signal_number(&self) -> Option<i32>;
+
+    /// Returns the signal which terminated the process, or `default` if it
+    /// wasn't terminated by a signal. This is always `default` on Windows.
+    fn signal_or(&self, default: i32) -> i32;
+
+    /// Returns the exit code a shell would report for the process: its exit
+    /// code, or 128 plus the signal number if it was terminated by a signal.
+    fn shell_code(&self) -> i32;
+
+    /// Returns whether the process crashed rather than exiting or being
+    /// killed.
+    ///
+    /// On Unix this is whether it was terminated by one of `SIGSEGV`,
+    /// `SIGABRT`, `SIGBUS`, `SIGILL` or `SIGFPE`. On Windows this is a
+    /// heuristic: a process that dies of an unhandled exception exits with
+    /// the exception's `NTSTATUS` code, such as `0xC0000005` for an access
+    /// violation, so any exit code with the error severity bits (`0xC0000000`)
+    /// set counts as a crash. A process which deliberately exits with such a
+    /// code is misreported.
+    fn crashed(&self) -> bool;
+
+    /// Converts the status into an `ExitCode`, so that the current process
+    /// can exit the same way the child did, for example by returning it from
+    /// `main`.
+    ///
+    /// This follows `shell_code`, but an `ExitCode` only holds 8 bits: exit
+    /// codes are truncated to their low 8 bits as `exit` does on Unix, except
+    /// that a failure is never turned into 0, and signals map to 128 plus
+    /// the signal number clamped to 255.
+    fn to_exit_code(&self) -> ExitCode;
+
+    /// Returns whether `other` describes the same outcome as this status: the
+    /// same exit code, terminating signal or stop signal.
+    ///
+    /// Unlike `==`, this ignores incidental bits of the raw status word such
+    /// as whether a core was dumped, so two processes killed by `SIGSEGV`
+    /// compare equal either way.
+    fn same_outcome(&self, other: &ExitStatus) -> bool;
+}
+
+impl ExitStatusExt for ExitStatus {
+    fn exited(&self) -> bool {
+        imp::exited(self)
+    }
+
+    fn signaled(&self) -> bool {
+        imp::signaled(self)
+    }
+
+    fn stopped(&self) -> bool {
+        imp::stopped(self)
+    }
+
+    fn continued(&self) -> bool {
+        imp::continued(self)
+    }
+
+    fn stop_signal(&self) -> Option<i32> {
+        imp::stop_signal(self)
+    }
+
+    fn raw_wait_status(&self) -> Option<i32> {
+        imp::raw_wait_status(self)
+    }
+
+    fn code_or(&self, default: i32) -> i32 {
+        self.code().unwrap_or(default)
+    }
+
+    fn is_success_code(&self, ok_codes: &[i32]) -> bool {
+        match self.code() {
+            Some(code) => ok_codes.contains(&code),
+            None => false,
+        }
+    }
+
+    fn signal_number(&self) -> Option<i32> {
+        imp::term_signal(self)
+    }
+
+    fn signal_or(&self, default: i32) -> i32 {
+        self.signal_number().unwrap_or(default)
+    }
+
+    fn shell_code(&self) -> i32 {
+        match imp::term_signal(self) {
+            Some(signal) => 128 + signal,
+            None => self.code_or(0),
+        }
+    }
+
+    fn crashed(&self) -> bool {
+        imp::crashed(self)
+    }
+
+    fn to_exit_code(&self) -> ExitCode {
+        let code = match imp::term_signal(self) {
+            Some(signal) => cmp::min(128 + signal, 255) as u8,
+            None => match self.code_or(0) {
+                code if code & 0xff == 0 && code != 0 => 1,
+                code => code as u8,
+            },
+        };
+        ExitCode::from(code)
+    }
+
+    fn same_outcome(&self, other: &ExitStatus) -> bool {
+        self.code() == other.code()
+            && self.signal_number() == other.signal_number()
+            && self.stop_signal() == other.stop_signal()
+            && self.continued() == other.continued()
+    }
+}
+
+/// Extension methods for the standard `std::process::Output` type.
+pub trait OutputExt {
+    /// Returns whether both stdout and stderr are empty.
+    fn is_empty(&self) -> bool;
+
+    /// Returns the combined length of stdout and stderr.
+    fn total_len(&self) -> usize;
+
+    /// Appends `bytes` to stdout.
+    fn extend_stdout(&mut self, bytes: &[u8]);
+
+    /// Appends `bytes` to stderr.
+    fn extend_stderr(&mut self, bytes: &[u8]);
+
+    /// Appends the stdout and stderr of `other` to this output, and takes its
+    /// status, as the later of the two.
+    ///
+    /// This lets an `Output` accumulate the results of collecting output in
+    /// several chunks.
+    fn merge(&mut self, other: Output);
+}
+
+impl OutputExt for Output {
+    fn is_empty(&self) -> bool {
+        self.stdout.is_empty() && self.stderr.is_empty()
+    }
+
+    fn total_len(&self) -> usize {
+        self.stdout.len() + self.stderr.len()
+    }
+
+    fn extend_stdout(&mut self, bytes: &[u8]) {
+        self.stdout.extend_from_slice(bytes);
+    }
+
+    fn extend_stderr(&mut self, bytes: &[u8]) {
+        self.stderr.extend_from_slice(bytes);
+    }
+
+    fn merge(&mut self, other: Output) {
+        self.stdout.extend(other.stdout);
+        self.stderr.extend(other.stderr);
+        self.status = other.status;
+    }
+}
+
+/// Extension methods for the standard `std::process::Command` type.
+pub trait CommandExt {
+    /// Spawn this command as a child process inside of a new Job Object.
+    ///
+    /// The job is configured with `JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE`, so
+    /// killing it with `Job::kill`, or simply dropping it, terminates the child
+    /// along with every process the child itself spawned. This is the Windows
+    /// counterpart to killing a whole process group on Unix.
+    ///
+    /// Note that the child is assigned to the job just after it is spawned, so
+    /// any processes it creates before that point are not part of the job.
+    #[cfg(windows)]
+    fn spawn_in_job(&mut self) -> io::Result<(Child, Job)>;
+
+    /// Configure this command to spawn the child as the leader of a new
+    /// process group, so it can later be killed along with its descendants
+    /// through `ChildExt::kill_process_group`.
+    ///
+    /// This is equivalent to calling `setpgid(0, 0)` in the child. Note that
+    /// job-control shells may signal process groups independently, and that
+    /// a child in its own group no longer receives signals such as `SIGINT`
+    /// sent to the group of the controlling terminal.
+    #[cfg(unix)]
+    fn process_group_leader(&mut self) -> &mut Command;
+
+    /// Configure this command to spawn the child as the leader of a new
+    /// session, as with `setsid`.
+    ///
+    /// The child is then also the leader of a new process group, so it can be
+    /// killed along with its descendants through
+    /// `ChildExt::kill_process_group`, and this shouldn't be combined with
+    /// `process_group_leader`.
+    ///
+    /// This registers a `pre_exec` hook on the command, so it should be
+    /// called once when the command is built rather than before each spawn,
+    /// as every call adds another hook which runs in the child.
+    ///
+    /// Being in a new session, the child has no controlling terminal. It
+    /// isn't sent `SIGHUP` when the terminal is closed, nor `SIGINT` or
+    /// `SIGTSTP` when its user presses `^C` or `^Z`, and opening `/dev/tty`
+    /// fails. This isolation is usually what a supervised daemon wants.
+    #[cfg(unix)]
+    fn session_leader(&mut self) -> &mut Command;
+
+    /// Spawn this command and wait for it to exit, timing out after the
+    /// duration `dur` has elapsed.
+    ///
+    /// Unlike `wait_with_output`, this leaves the child alone on a timeout:
+    /// the `Child` is returned along with `None`, and it's up to the caller
+    /// to keep waiting, kill it or let it run. The child's stdio is set up as
+    /// configured on the command, except that a piped stdin is closed before
+    /// waiting, as with `ChildExt::wait_timeout`.
+    fn spawn_and_wait(&mut self, dur: Duration) -> io::Result<(Child, Option<ExitStatus>)>;
+
+    /// Spawn this command and collect all of its output, killing it if it
+    /// doesn't finish within `dur`.
+    ///
+    /// The child's stdin is set to null and its stdout and stderr are piped
+    /// and drained concurrently, so a child filling one pipe can't deadlock
+    /// against the other. If the timeout elapses before the child exits and
+    /// closes its output, the child is killed and reaped and an error of kind
+    /// `io::ErrorKind::TimedOut` is returned. The timeout covers reading the
+    /// output too, so this also happens if the child exits but a descendant
+    /// which inherited its pipes keeps them open; with
+    /// `OutputOptions::stdout_eof_grace` the output is returned instead. If
+    /// reading the output fails, the error wraps a `ReadError` telling whether
+    /// the child had already exited.
+    fn wait_with_output(&mut self, dur: Duration) -> io::Result<Output>;
+
+    /// Same as `wait_with_output`, but also returns how long it took, from
+    /// just before the child was spawned until all of its output was
+    /// collected.
+    fn wait_with_output_timed(&mut self, dur: Duration) -> io::Result<(Output, Duration)>;
+
+    /// Same as `wait_with_output`, but feeds `input` to the child's stdin.
+    ///
+    /// The input is written from a separate thread while the output is
+    /// collected, and stdin is closed once all of it has been written, so
+    /// filters which only produce output after reading all of their input work
+    /// as expected. If the child exits without reading all of its input, the
+    /// rest is discarded.
+    fn wait_with_input_output(&mut self, input: &[u8], dur: Duration) -> io::Result<Output>;
+
+    /// Same as `wait_with_output`, but with further control over how the
+    /// output is collected as described by `options`.
+    fn wait_with_output_opts(
+        &mut self,
+        dur: Duration,
+        options: &OutputOptions,
+    ) -> io::Result<Output>;
+
+    /// Same as `wait_with_output`, but returns early without killing the
+    /// child once `interrupt` is set.
+    ///
+    /// If the interrupt is set first, `Collected::Interrupted` hands back the
+    /// still running child along with the output collected so far. This is
+    /// useful for a user interface which stops watching a process without
+    /// stopping the process itself.
+    fn wait_with_output_interruptible(
+        &mut self,
+        dur: Duration,
+        interrupt: &Interrupt,
+    ) -> io::Result<Collected>;
+
+    /// Same as `wait_with_output`, but streams the child's stdout and stderr
+    /// into the given writers instead of buffering them, and returns only its
+    /// status.
+    ///
+    /// Output is written as it's read rather than accumulated in memory, which
+    /// suits children printing large amounts of output straight to log files.
+    /// Short writes are retried until everything is written, and if the
+    /// writers can't keep up, only a few chunks of output are queued before
+    /// the child is throttled by its pipes filling up.
+    ///
+    /// The writers are expected to block: if writing fails, including with
+    /// `io::ErrorKind::WouldBlock`, the child is killed and reaped, and the
+    /// error is returned. Whatever was written before a timeout stays written.
+    fn wait_with_output_to<O, E>(
+        &mut self,
+        dur: Duration,
+        stdout: O,
+        stderr: E,
+    ) -> io::Result<ExitStatus>
+    where
+        O: Write,
+        E: Write,
+        Self: Sized;
+}
+
+impl CommandExt for Command {
+    #[cfg(windows)]
+    fn spawn_in_job(&mut self) -> io::Result<(Child, Job)> {
+        let job = imp::Job::new()?;
+        let mut child = self.spawn()?;
+        if let Err(e) = job.assign(&child) {
+            drop(child.kill());
+            drop(child.wait());
+            return Err(e);
+        }
+        Ok((child, Job { inner: job }))
+    }
+
+    #[cfg(unix)]
+    fn process_group_leader(&mut self) -> &mut Command {
+        std::os::unix::process::CommandExt::process_group(self, 0)
+    }
+
+    #[cfg(unix)]
+    fn session_leader(&mut self) -> &mut Command {
+        use std::os::unix::process::CommandExt;
+
+        // Hooks accumulate if this is called repeatedly on the same command,
+        // so a child which already leads its session is left alone.
+        unsafe {
+            self.pre_exec(|| {
+                if libc::getsid(0) != libc::getpid() && libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            })
+        }
+    }
+
+    fn spawn_and_wait(&mut self, dur: Duration) -> io::Result<(Child, Option<ExitStatus>)> {
+        let mut child = self.spawn()?;
+        let status = child.wait_timeout(dur)?;
+        Ok((child, status))
+    }
+
+    fn wait_with_output(&mut self, dur: Duration) -> io::Result<Output> {
+        let options = OutputOptions::default();
+        output::wait_with_output(self, None, dur, &options, None).map(exited)
+    }
+
+    fn wait_with_output_timed(&mut self, dur: Duration) -> io::Result<(Output, Duration)> {
+        let start = Instant::now();
+        let output = self.wait_with_output(dur)?;
+        Ok((output, start.elapsed()))
+    }
+
+    fn wait_with_input_output(&mut self, input: &[u8], dur: Duration) -> io::Result<Output> {
+        let options = OutputOptions::default();
+        output::wait_with_output(self, Some(input), dur, &options, None).map(exited)
+    }
+
+    fn wait_with_output_opts(
+        &mut self,
+        dur: Duration,
+        options: &OutputOptions,
+    ) -> io::Result<Output> {
+        output::wait_with_output(self, None, dur, options, None).map(exited)
+    }
+
+    fn wait_with_output_interruptible(
+        &mut self,
+        dur: Duration,
+        interrupt: &Interrupt,
+    ) -> io::Result<Collected> {
+        let options = OutputOptions::default();
+        output::wait_with_output(self, None, dur, &options, Some(interrupt))
+    }
+
+    fn wait_with_output_to<O, E>(
+        &mut self,
+        dur: Duration,
+        mut stdout: O,
+        mut stderr: E,
+    ) -> io::Result<ExitStatus>
+    where
+        O: Write,
+        E: Write,
+    {
+        let options = OutputOptions::default();
+        let finished =
+            output::wait_with_output_to(self, None, dur, &options, None, &mut stdout, &mut stderr)?;
+        match finished {
+            output::Finished::Exited(status) => Ok(status),
+            output::Finished::Interrupted(..) => unreachable!(),
+        }
+    }
+}
+
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
+
+// Reports a collection which couldn't grow as requested, such as for an
+// unreasonable capacity passed in by the caller.
+fn out_of_memory(err: TryReserveError) -> io::Error {
+    io::Error::new(io::ErrorKind::OutOfMemory, err)
+}
+
+// Unwraps the output of a collection which can't have been interrupted.
+fn exited(collected: Collected) -> Output {
+    match collected {
+        Collected::Exited(output) => output,
+        Collected::Interrupted { .. } => unreachable!(),
+    }
+}
+
+/// A Windows Job Object containing a child process and its descendants.
+///
+/// This is created by `CommandExt::spawn_in_job`. All processes in the job are
+/// terminated when this is dropped.
+#[cfg(windows)]
+pub struct Job {
+    inner: imp::Job,
+}
+
+#[cfg(windows)]
+impl Job {
+    /// Terminates every process in this job.
+    pub fn kill(&self) -> io::Result<()> {
+        self.inner.kill()
+    }
+}
+
+/// Wait for the process identified by `handle` to exit, timing out after the
+/// duration `dur` has elapsed.
+///
+/// This is useful for processes which weren't spawned as a `Child` of this
+/// process, for example a handle acquired through `OpenProcess`. The handle
+/// must have the `SYNCHRONIZE` and `PROCESS_QUERY_INFORMATION` access rights,
+/// and it remains owned by the caller.
+///
+/// The return value has the same meaning as `ChildExt::wait_timeout`.
+#[cfg(windows)]
+pub fn wait_handle_timeout(handle: RawHandle, dur: Duration) -> io::Result<Option<ExitStatus>> {
+    imp::wait_handle_timeout(handle, dur)
+}
+
+/// Wait for the process referred to by `pidfd` to exit, timing out after the
+/// duration `dur` has elapsed.
+///
+/// This is useful for children spawned through a mechanism which already
+/// hands out a pidfd, such as `clone3` with `CLONE_PIDFD`, and needs no signal
+/// handler at all. The process must be a child of this process, as it's
+/// reaped once it exits, and `pidfd` remains owned by the caller. Reaping
+/// through a pidfd requires Linux 5.4 or later.
+///
+/// The return value has the same meaning as `ChildExt::wait_timeout`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn wait_pidfd_timeout(
+    pidfd: std::os::unix::io::RawFd,
+    dur: Duration,
+) -> io::Result<Option<ExitStatus>> {
+    imp::wait_pidfd_timeout(pidfd, dur)
+}
+
+/// Run `cmd` to completion with `CommandExt::wait_with_output`, retrying up to
+/// `attempts` times when it times out, all within a total budget of `total`.
+///
+/// Each attempt is given whatever is left of the budget, and a timed out
+/// attempt is killed before the next one starts. Once the attempts or the
+/// budget are exhausted the last timeout error is returned. Other errors, such
+/// as failing to spawn, are returned right away, and so is the output of the
+/// first attempt which finishes, regardless of its exit status.
+pub fn retry_timeout(cmd: &mut Command, attempts: usize, total: Duration) -> io::Result<Output> {
+    output::retry_timeout(cmd, attempts, total)
+}
+
+/// Kill a child process along with all of its descendants.
+///
+/// This is a best-effort operation which differs across platforms:
+///
+/// * On Linux the process tree is discovered by walking `/proc`. The tree is
+///   stopped with `SIGSTOP` while it's walked so that it can't spawn new
+///   processes, and then everything is killed with `SIGKILL`. Descendants that
+///   were already reparented away from the tree, for example after their
+///   parent exited, can't be found.
+/// * On other Unix platforms only the process group led by the child is killed,
+///   so descendants are only covered if the child was spawned as a process
+///   group leader, see `CommandExt::process_group_leader`.
+/// * On Windows the process tree is discovered through a snapshot of all
+///   processes. Windows recycles process identifiers aggressively, so a
+///   process whose parent exited may be missed, or in rare cases an unrelated
+///   process may be matched. Prefer `CommandExt::spawn_in_job` when possible.
+///
+/// If the child has already exited nothing is signalled, as its identifier may
+/// since have been reused. Otherwise the child itself is always killed, but it
+/// isn't reaped, so it should still be waited on afterwards.
+pub fn kill_tree(child: &mut Child) -> io::Result<()> {
+    if child.try_wait()?.is_some() {
+        return Ok(());
+    }
+    imp::kill_tree(child)?;
+    child.kill()
+}
+
+/// A guard which kills a child process once a timeout elapses.
+///
+/// This is created by `ChildExt::kill_after`, and the kill is cancelled if the
+/// guard is dropped before the timeout elapses. The child can be waited on
+/// through the guard, in which case the kill is coordinated with the wait.
+pub struct KillGuard<'a> {
+    child: &'a mut Child,
+    killer: imp::Killer,
+    // `None` once there's nothing left to kill, either because the child had
+    // already exited or because the kill has been sent, or if the timeout is
+    // too far out to ever elapse.
+    deadline: Option<Instant>,
+    cancel: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl<'a> KillGuard<'a> {
+    fn new(
+        child: &'a mut Child,
+        killer: imp::Killer,
+        deadline: Option<Instant>,
+    ) -> io::Result<KillGuard<'a>> {
+        let mut guard = KillGuard {
+            child,
+            killer,
+            deadline,
+            cancel: None,
+            thread: None,
+        };
+        guard.start()?;
+        Ok(guard)
+    }
+
+    /// Wait for the child to exit, killing it once the guard's timeout
+    /// elapses.
+    ///
+    /// The timer is stopped while this waits, and the kill is instead sent by
+    /// this call, so the child is never reaped while a kill is still pending.
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.stop();
+        if let Some(deadline) = self.deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if let Some(status) = self.child.wait_timeout(left)? {
+                self.deadline = None;
+                return Ok(status);
+            }
+            self.fire();
+        }
+        self.child.wait()
+    }
+
+    /// Wait for the child to exit for at most `dur`, killing it if the
+    /// guard's timeout elapses in the meantime.
+    ///
+    /// Returns `None` if the child is still running after `dur`, in which
+    /// case the kill stays scheduled for the guard's original deadline. Like
+    /// `wait` the timer is stopped while this waits, so the child is never
+    /// reaped while a kill is still pending.
+    pub fn wait_timeout(&mut self, dur: Duration) -> io::Result<Option<ExitStatus>> {
+        self.stop();
+        let end = Instant::now().checked_add(dur);
+        if let Some(deadline) = self.deadline {
+            if end.is_some_and(|end| end < deadline) {
+                let status = self.child.wait_timeout(dur)?;
+                if status.is_some() {
+                    self.deadline = None;
+                } else {
+                    self.start()?;
+                }
+                return Ok(status);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if let Some(status) = self.child.wait_timeout(left)? {
+                self.deadline = None;
+                return Ok(Some(status));
+            }
+            self.fire();
+        }
+        match end {
+            Some(end) => self
+                .child
+                .wait_timeout(end.saturating_duration_since(Instant::now())),
+            None => self.child.wait().map(Some),
+        }
+    }
+
+    // Sends the kill from this thread, which can't race with a reap as it's
+    // the one holding on to the child.
+    fn fire(&mut self) {
+        self.killer.kill();
+        self.deadline = None;
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return Ok(()),
+        };
+        let killer = self.killer;
+        let (tx, rx) = mpsc::channel::<()>();
+        let thread = thread::Builder::new()
+            .name("wait-timeout-kill".to_string())
+            .spawn(move || {
+                let dur = deadline.saturating_duration_since(Instant::now());
+                if let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(dur) {
+                    killer.kill();
+                }
+            })?;
+        self.cancel = Some(tx);
+        self.thread = Some(thread);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        // Disconnecting the channel wakes up the timer thread, and joining it
+        // guarantees the kill can't happen after the child is released.
+        drop(self.cancel.take());
+        if let Some(thread) = self.thread.take() {
+            drop(thread.join());
+        }
+    }
+}
+
+impl<'a> Deref for KillGuard<'a> {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        self.child
+    }
+}
+
+impl<'a> Drop for KillGuard<'a> {
+    fn drop(&mut self) {
+        self.stop();
     }
 }
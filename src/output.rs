@@ -0,0 +1,470 @@
+//! Collecting the output of a child process within a timeout.
+//!
+//! Each of the child's output pipes is drained by a helper thread which
+//! forwards chunks over a channel, so both streams make progress concurrently
+//! and the calling thread can enforce deadlines with `recv_timeout`.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use {imp, out_of_memory, Interrupt};
+
+// How many chunks read from the child may be waiting to be written out.
+const QUEUED_CHUNKS: usize = 16;
+
+/// Options controlling how `CommandExt::wait_with_output_opts` collects the
+/// output of a child.
+#[derive(Clone, Debug, Default)]
+pub struct OutputOptions {
+    stdout_eof_grace: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    keep_stdout: bool,
+    keep_stderr: bool,
+    stdout_capacity: usize,
+    stderr_capacity: usize,
+}
+
+impl OutputOptions {
+    /// Creates a new set of options with the default behavior of collecting
+    /// both streams until they are closed.
+    pub fn new() -> OutputOptions {
+        OutputOptions::default()
+    }
+
+    /// Stop collecting stderr once `grace` has elapsed after stdout is closed.
+    ///
+    /// This is intended for protocols where stdout is the payload and stderr
+    /// is only diagnostic: a child (or a grandchild which inherited the pipe)
+    /// that keeps stderr open after finishing its output doesn't hold up the
+    /// call. Whatever was read from stderr within the grace period is still
+    /// returned. The child itself must still exit within the overall timeout.
+    pub fn stdout_eof_grace(&mut self, grace: Duration) -> &mut OutputOptions {
+        self.stdout_eof_grace = Some(grace);
+        self
+    }
+
+    /// Kill the child if it goes `idle` without writing anything to the
+    /// captured stdout or stderr.
+    ///
+    /// This catches children which hang well within a generous overall
+    /// timeout, such as a stuck build. The window restarts whenever output is
+    /// read, and keeps running once both streams are closed, so a child that
+    /// closes its output and then doesn't exit is caught too. Output from a
+    /// stream kept with `keep_stdout` or `keep_stderr` isn't seen, and doesn't
+    /// count as activity. When the child is killed for being idle, an error
+    /// of kind `io::ErrorKind::TimedOut` is returned, as for the overall
+    /// timeout.
+    pub fn idle_timeout(&mut self, idle: Duration) -> &mut OutputOptions {
+        self.idle_timeout = Some(idle);
+        self
+    }
+
+    /// Keep the stdout configuration of the `Command` rather than capturing
+    /// it, for example to let it inherit the parent's stdout.
+    ///
+    /// The returned `Output` then only contains stdout if it was configured
+    /// as piped.
+    pub fn keep_stdout(&mut self, keep: bool) -> &mut OutputOptions {
+        self.keep_stdout = keep;
+        self
+    }
+
+    /// Keep the stderr configuration of the `Command` rather than capturing
+    /// it, for example to let it inherit the parent's stderr.
+    ///
+    /// The returned `Output` then only contains stderr if it was configured
+    /// as piped.
+    pub fn keep_stderr(&mut self, keep: bool) -> &mut OutputOptions {
+        self.keep_stderr = keep;
+        self
+    }
+
+    /// Reserve room for `capacity` bytes of stdout up front, so collecting
+    /// output of about that size doesn't have to grow the buffer repeatedly.
+    ///
+    /// This is only a hint: more output than this is still collected. It
+    /// doesn't apply when the output is written to a writer with
+    /// `CommandExt::wait_with_output_to`.
+    pub fn stdout_capacity(&mut self, capacity: usize) -> &mut OutputOptions {
+        self.stdout_capacity = capacity;
+        self
+    }
+
+    /// Same as `stdout_capacity`, but for stderr.
+    pub fn stderr_capacity(&mut self, capacity: usize) -> &mut OutputOptions {
+        self.stderr_capacity = capacity;
+        self
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+enum Event {
+    Data(Stream, Vec<u8>),
+    Eof(Stream),
+    Error(io::Error),
+    Interrupted,
+}
+
+/// The result of `CommandExt::wait_with_output_interruptible`.
+#[derive(Debug)]
+pub enum Collected {
+    /// The child exited and closed its output.
+    Exited(Output),
+    /// The interrupt was set before the child exited.
+    Interrupted {
+        /// The child, which is left running. Its output pipes are drained and
+        /// discarded in the background from now on, so it never blocks
+        /// writing to them.
+        child: Child,
+        /// Everything read from stdout before the interrupt.
+        stdout: Vec<u8>,
+        /// Everything read from stderr before the interrupt.
+        stderr: Vec<u8>,
+    },
+}
+
+/// The error behind an `io::Error` returned when reading a child's output
+/// fails, along with the child's status if it had already exited.
+///
+/// Reading can fail after the child has finished, in which case its status is
+/// still worth knowing. The returned `io::Error` has the same kind as the read
+/// error and wraps this type, which can be recovered with
+/// `io::Error::get_ref` and `downcast_ref`. A child which was still running is
+/// killed and reaped, and no status is reported for it.
+#[derive(Debug)]
+pub struct ReadError {
+    status: Option<ExitStatus>,
+    error: io::Error,
+}
+
+impl ReadError {
+    /// Returns the status of the child if it had exited on its own by the time
+    /// reading failed.
+    pub fn status(&self) -> Option<ExitStatus> {
+        self.status
+    }
+
+    /// Returns the error which reading the output failed with.
+    pub fn error(&self) -> &io::Error {
+        &self.error
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to read child output: {}", self.error)?;
+        if let Some(status) = self.status {
+            write!(f, " (child already exited: {})", status)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+// How the wait in `wait_with_output_to` ended.
+pub enum Finished {
+    Exited(ExitStatus),
+    Interrupted(Child),
+}
+
+pub fn wait_with_output(
+    cmd: &mut Command,
+    input: Option<&[u8]>,
+    dur: Duration,
+    options: &OutputOptions,
+    interrupt: Option<&Interrupt>,
+) -> io::Result<Collected> {
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    stdout
+        .try_reserve_exact(options.stdout_capacity)
+        .map_err(out_of_memory)?;
+    stderr
+        .try_reserve_exact(options.stderr_capacity)
+        .map_err(out_of_memory)?;
+    let finished = wait_with_output_to(
+        cmd,
+        input,
+        dur,
+        options,
+        interrupt,
+        &mut stdout,
+        &mut stderr,
+    )?;
+    Ok(match finished {
+        Finished::Exited(status) => Collected::Exited(Output {
+            status,
+            stdout,
+            stderr,
+        }),
+        Finished::Interrupted(child) => Collected::Interrupted {
+            child,
+            stdout,
+            stderr,
+        },
+    })
+}
+
+// Same as `wait_with_output`, but writes the output to `stdout` and `stderr`
+// as it's read instead of buffering it.
+pub fn wait_with_output_to(
+    cmd: &mut Command,
+    input: Option<&[u8]>,
+    dur: Duration,
+    options: &OutputOptions,
+    interrupt: Option<&Interrupt>,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> io::Result<Finished> {
+    let stdin = if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+    cmd.stdin(stdin);
+    if !options.keep_stdout {
+        cmd.stdout(Stdio::piped());
+    }
+    if !options.keep_stderr {
+        cmd.stderr(Stdio::piped());
+    }
+    let mut child = cmd.spawn()?;
+    if let Some(input) = input {
+        let stdin = child.stdin.take().unwrap();
+        if let Err(e) = spawn_writer(stdin, input.to_vec()) {
+            kill_and_reap(&mut child);
+            return Err(e);
+        }
+    }
+    collect(child, dur, options, interrupt, stdout, stderr)
+}
+
+pub fn retry_timeout(cmd: &mut Command, attempts: usize, total: Duration) -> io::Result<Output> {
+    let start = Instant::now();
+    let mut last = None;
+    for attempt in 0..attempts {
+        let left = total.saturating_sub(start.elapsed());
+        if left == Duration::ZERO {
+            break;
+        }
+        let options = OutputOptions::default();
+        match wait_with_output(cmd, None, left, &options, None) {
+            Ok(Collected::Exited(output)) => return Ok(output),
+            Ok(Collected::Interrupted { .. }) => unreachable!(),
+            Err(e) => {
+                if e.kind() != io::ErrorKind::TimedOut {
+                    return Err(e);
+                }
+                trace!("attempt {} timed out", attempt + 1);
+                last = Some(e);
+            }
+        }
+    }
+    Err(last.unwrap_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "no attempt could be made")))
+}
+
+fn collect(
+    mut child: Child,
+    dur: Duration,
+    options: &OutputOptions,
+    interrupt: Option<&Interrupt>,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> io::Result<Finished> {
+    // A deadline too far in the future to represent is the same as none.
+    let deadline = Instant::now().checked_add(dur);
+
+    // The channel is bounded so that output which can't be written out as
+    // fast as the child produces it isn't queued up without limit: the
+    // readers stall instead, and eventually so does the child on its pipes.
+    let (tx, rx) = mpsc::sync_channel(QUEUED_CHUNKS);
+    let mut stdout_open = false;
+    let mut stderr_open = false;
+    if let Some(stdout) = child.stdout.take() {
+        if let Err(e) = spawn_reader(Stream::Stdout, stdout, tx.clone()) {
+            kill_and_reap(&mut child);
+            return Err(e);
+        }
+        stdout_open = true;
+    }
+    if let Some(stderr) = child.stderr.take() {
+        if let Err(e) = spawn_reader(Stream::Stderr, stderr, tx.clone()) {
+            kill_and_reap(&mut child);
+            return Err(e);
+        }
+        stderr_open = true;
+    }
+    let waker = interrupt.map(|i| {
+        let tx = tx.clone();
+        // Never block the interrupting thread on a full channel, the flag is
+        // also checked before each receive.
+        i.on_interrupt(Box::new(move || drop(tx.try_send(Event::Interrupted))))
+    });
+    drop(tx);
+
+    let mut stderr_deadline = None;
+    let idle_from_now = || {
+        options
+            .idle_timeout
+            .and_then(|i| Instant::now().checked_add(i))
+    };
+    let mut idle_deadline = idle_from_now();
+    while stdout_open || stderr_open {
+        if interrupt.is_some_and(|i| i.is_interrupted()) {
+            return Ok(Finished::Interrupted(child));
+        }
+        // Once only stderr is left its grace period also bounds the wait, and
+        // when that runs out we simply stop reading it.
+        let (until, grace) = match stderr_deadline {
+            Some(d) if !stdout_open && deadline.is_none_or(|end| d < end) => (Some(d), true),
+            _ => (deadline, false),
+        };
+        let (until, idle) = match idle_deadline {
+            Some(d) if until.is_none_or(|end| d < end) => (Some(d), true),
+            _ => (until, false),
+        };
+        match recv(&rx, until) {
+            Ok(Event::Data(stream, data)) => {
+                idle_deadline = idle_from_now();
+                let written = match stream {
+                    Stream::Stdout => stdout.write_all(&data),
+                    Stream::Stderr => stderr.write_all(&data),
+                };
+                if let Err(e) = written {
+                    kill_and_reap(&mut child);
+                    return Err(e);
+                }
+            }
+            Ok(Event::Eof(Stream::Stdout)) => {
+                stdout_open = false;
+                stderr_deadline = options
+                    .stdout_eof_grace
+                    .and_then(|g| Instant::now().checked_add(g));
+            }
+            Ok(Event::Eof(Stream::Stderr)) => stderr_open = false,
+            Ok(Event::Error(e)) => return Err(read_failed(&mut child, e)),
+            Ok(Event::Interrupted) => return Ok(Finished::Interrupted(child)),
+            Err(RecvTimeoutError::Timeout) if idle => return Err(went_idle(&mut child, options)),
+            Err(RecvTimeoutError::Timeout) if grace => break,
+            Err(RecvTimeoutError::Timeout) => return Err(timed_out(&mut child)),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    drop(waker);
+
+    drop(child.stdin.take());
+    let inner = interrupt.map(|i| &*i.inner);
+    let idle = idle_deadline.is_some_and(|d| deadline.is_none_or(|end| d < end));
+    let until = if idle { idle_deadline } else { deadline };
+    match imp::wait_timeout(&mut child, remaining(until), inner)? {
+        Some(exit) => Ok(Finished::Exited(exit.status)),
+        None if interrupt.is_some_and(|i| i.is_interrupted()) => Ok(Finished::Interrupted(child)),
+        None if idle => Err(went_idle(&mut child, options)),
+        None => Err(timed_out(&mut child)),
+    }
+}
+
+// Reads `pipe` to completion on a new thread, forwarding everything read to
+// `tx`. The thread is detached: once the receiver is gone it keeps draining
+// the pipe and discarding what it reads, so a child left running never blocks
+// on a full pipe, and it lingers until the pipe is finally closed.
+fn spawn_reader<R>(stream: Stream, mut pipe: R, tx: SyncSender<Event>) -> io::Result<()>
+where
+    R: Read + Send + 'static,
+{
+    thread::Builder::new()
+        .name("wait-timeout-output".to_string())
+        .spawn(move || {
+            let mut buf = [0; 8192];
+            loop {
+                let event = match pipe.read(&mut buf) {
+                    Ok(0) => Event::Eof(stream),
+                    Ok(n) => Event::Data(stream, buf[..n].to_vec()),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => Event::Error(e),
+                };
+                let done = !matches!(event, Event::Data(..));
+                drop(tx.send(event));
+                if done {
+                    break;
+                }
+            }
+        })?;
+    Ok(())
+}
+
+// Writes `input` to the child's stdin on a new thread, so the child can't
+// deadlock against us by filling its output pipes before it has read all of
+// its input. The pipe is closed once everything is written. A child which
+// exits without reading everything just breaks the pipe, which is ignored.
+fn spawn_writer<W>(mut pipe: W, input: Vec<u8>) -> io::Result<()>
+where
+    W: Write + Send + 'static,
+{
+    thread::Builder::new()
+        .name("wait-timeout-input".to_string())
+        .spawn(move || drop(pipe.write_all(&input)))?;
+    Ok(())
+}
+
+fn recv(rx: &Receiver<Event>, deadline: Option<Instant>) -> Result<Event, RecvTimeoutError> {
+    match deadline {
+        Some(_) => rx.recv_timeout(remaining(deadline)),
+        None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    }
+}
+
+fn remaining(deadline: Option<Instant>) -> Duration {
+    match deadline {
+        Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+        None => Duration::MAX,
+    }
+}
+
+// The child is reaped even if killing it fails, since that's usually because
+// it exited on its own in the meantime and is waiting to be reaped.
+fn kill_and_reap(child: &mut Child) {
+    drop(child.kill());
+    drop(child.wait());
+}
+
+fn read_failed(child: &mut Child, error: io::Error) -> io::Error {
+    // Check whether the child finished on its own before killing it.
+    let status = child.try_wait().ok().flatten();
+    kill_and_reap(child);
+    io::Error::new(error.kind(), ReadError { status, error })
+}
+
+fn went_idle(child: &mut Child, options: &OutputOptions) -> io::Error {
+    kill_and_reap(child);
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!(
+            "child produced no output for {:?}",
+            options.idle_timeout.unwrap()
+        ),
+    )
+}
+
+fn timed_out(child: &mut Child) -> io::Error {
+    kill_and_reap(child);
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        "timed out waiting for child output",
+    )
+}
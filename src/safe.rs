@@ -0,0 +1,25 @@
+//! A curated subset of this crate for callers which want to audit as little as
+//! possible.
+//!
+//! Everything re-exported here reports failures through `io::Result` instead
+//! of panicking, including on unreasonable input such as an unhandleable
+//! `WaiterOptions::child_signal` or a huge capacity, which are rejected with
+//! an error of kind `io::ErrorKind::InvalidInput` or
+//! `io::ErrorKind::OutOfMemory` respectively.
+//!
+//! Left out are `wait_pidfd_timeout` and `wait_handle_timeout`, which act on a
+//! descriptor or handle supplied by the caller that can't be validated to
+//! refer to the intended process, and `kill_tree`, whose process tree
+//! discovery may match unrelated processes on Windows.
+
+pub use output::{Collected, OutputOptions, ReadError};
+pub use {
+    retry_timeout, ChildExt, CommandExt, ExitStatusExt, Interrupt, KillGuard, OutputExt, Reaped,
+    TryWaitResult, WaitResult, WaitTimeoutResult,
+};
+
+#[cfg(unix)]
+pub use {Activity, WaitOrRead, WaitSnapshot, Waiter, WaiterOptions};
+
+#[cfg(windows)]
+pub use Job;
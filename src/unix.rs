@@ -14,73 +14,1092 @@
 //! Signal handling is super tricky in general, and this is no exception. Due
 //! to the async nature of SIGCHLD, we use the self-pipe trick to transmit
 //! data out of the signal handler to the rest of the application.
+//!
+//! On Linux 5.3 and later none of this is needed though: a pidfd refers to a
+//! single process and becomes readable once it exits, so we can simply poll
+//! it with a timeout. The signal handler is then only installed if a `Waiter`
+//! is created.
 
 #![allow(bad_style)]
 
 use std::cmp;
 use std::collections::HashMap;
+use std::env;
+use std::fmt;
 use std::io::{self, Read, Write};
 use std::mem;
 use std::os::unix::net::UnixStream;
 use std::os::unix::prelude::*;
 use std::process::{Child, ExitStatus};
-use std::sync::{Mutex, Once};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard, Once, OnceLock};
 use std::time::{Duration, Instant};
 
 use libc::{self, c_int};
 
+use {out_of_memory, Exit, Reaped};
+
 static INIT: Once = Once::new();
-static mut STATE: *mut State = 0 as *mut _;
+static mut STATE: *mut State = ptr::null_mut();
 
 struct State {
+    signal: c_int,
     prev: libc::sigaction,
     write: UnixStream,
     read: UnixStream,
     map: Mutex<StateMap>,
 }
 
-type StateMap = HashMap<*mut Child, (UnixStream, Option<ExitStatus>)>;
+type StateMap = HashMap<*mut Child, (UnixStream, Option<Exit>)>;
+
+// Run by `install` right after the handler is installed, so tests can deliver
+// a signal at the first moment the handler may run, whatever else
+// `State::init` has or hasn't done by then.
+#[cfg(test)]
+static INIT_PAUSE: Mutex<Option<fn()>> = Mutex::new(None);
+
+// Installs `action` as the handler for `signal`, after which it may run at any
+// moment.
+unsafe fn install(signal: c_int, action: &libc::sigaction) {
+    assert_eq!(libc::sigaction(signal, action, ptr::null_mut()), 0);
+
+    #[cfg(test)]
+    {
+        let pause = *INIT_PAUSE.lock().unwrap();
+        if let Some(pause) = pause {
+            pause();
+        }
+    }
+}
+
+// The write halves of the pipes of every live `Waiter`, which the signal
+// handler notifies alongside the self-pipe. Free slots hold -1.
+//
+// `HANDLERS` counts signal handlers currently running, so a waiter being
+// dropped can wait for any handler which may have loaded its descriptor before
+// the descriptor is closed (and possibly reused).
+const MAX_WAITERS: usize = 64;
+static WAITERS: [AtomicI32; MAX_WAITERS] = [const { AtomicI32::new(-1) }; MAX_WAITERS];
+static HANDLERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Configuration of the process-wide state, which is fixed by whichever caller
+/// initializes it first.
+#[derive(Clone, Debug)]
+pub struct Options {
+    // `None` means `SIGCHLD`, without insisting on it.
+    pub child_signal: Option<c_int>,
+    pub restart: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            child_signal: None,
+            restart: true,
+        }
+    }
+}
+
+pub fn init(options: &Options) -> io::Result<()> {
+    // Initialization can't fail, as that would poison `INIT` for every later
+    // wait, so the signal is vetted and the self-pipe created up front. The
+    // pipe is simply dropped if another thread wins the race to initialize.
+    if let Some(signal) = options.child_signal {
+        check_signal(signal)?;
+    }
+    if !INIT.is_completed() {
+        let (read, write) = UnixStream::pair()?;
+        read.set_nonblocking(true)?;
+        write.set_nonblocking(true)?;
+        INIT.call_once(move || State::init(options, read, write));
+    }
+
+    // Only one handler is ever installed, so a request for another signal
+    // than the one it was installed for can't be honored.
+    let signal = unsafe { (*STATE).signal };
+    match options.child_signal {
+        Some(requested) if requested != signal => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "child exit handler already installed for signal {}, not {}",
+                signal, requested
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Fails unless a handler can be installed for `signal`.
+fn check_signal(signal: c_int) -> io::Result<()> {
+    let valid = signal != libc::SIGKILL
+        && signal != libc::SIGSTOP
+        && unsafe {
+            let mut old: libc::sigaction = mem::zeroed();
+            libc::sigaction(signal, ptr::null(), &mut old) == 0
+        };
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("can't handle child exits with signal {}", signal),
+        ))
+    }
+}
+
+/// A set of signals blocked on the calling thread while a `Waiter` blocks.
+#[derive(Clone, Copy)]
+pub struct SignalMask {
+    set: libc::sigset_t,
+}
+
+impl SignalMask {
+    // The handler's own signal is never blocked, as the waits of the signal
+    // based implementation rely on it being delivered. Without a handler
+    // nothing relies on a signal, and pidfds are used to wait.
+    pub fn new(signals: &[c_int]) -> io::Result<SignalMask> {
+        unsafe {
+            let mut set = mem::zeroed();
+            libc::sigemptyset(&mut set);
+            for &signal in signals {
+                if libc::sigaddset(&mut set, signal) == -1 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid signal {}", signal),
+                    ));
+                }
+            }
+            if INIT.is_completed() {
+                libc::sigdelset(&mut set, (*STATE).signal);
+            }
+            Ok(SignalMask { set })
+        }
+    }
+
+    // Blocks the signals until the returned guard is dropped, which restores
+    // the previous mask. Since every wait is woken up through a descriptor
+    // rather than by a signal interrupting it, blocking signals around the
+    // wait can't make it miss anything.
+    pub fn block(&self) -> MaskGuard {
+        unsafe {
+            let mut prev = mem::zeroed();
+            libc::pthread_sigmask(libc::SIG_BLOCK, &self.set, &mut prev);
+            MaskGuard { prev }
+        }
+    }
+}
+
+impl fmt::Debug for SignalMask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SignalMask").finish_non_exhaustive()
+    }
+}
+
+pub struct MaskGuard {
+    prev: libc::sigset_t,
+}
+
+impl Drop for MaskGuard {
+    fn drop(&mut self) {
+        unsafe {
+            libc::pthread_sigmask(libc::SIG_SETMASK, &self.prev, ptr::null_mut());
+        }
+    }
+}
+
+// Makes room for `additional` more concurrent waits in the map of waiting
+// children.
+pub fn reserve(additional: usize) -> io::Result<()> {
+    if uses_pidfds(&Options::default()) {
+        return Ok(());
+    }
+    init(&Options::default())?;
+    unsafe { (*STATE).lock().try_reserve(additional) }.map_err(out_of_memory)
+}
+
+pub fn wait_timeout(
+    child: &mut Child,
+    dur: Duration,
+    interrupt: Option<&Interrupt>,
+) -> io::Result<Option<Exit>> {
+    wait(child, dur, interrupt, [-1, -1])
+}
+
+// Same as `wait_timeout`, but also stops waiting once either of `fds` becomes
+// readable. Negative descriptors are ignored.
+pub fn wait_or_readable(
+    child: &mut Child,
+    fds: [RawFd; 2],
+    dur: Duration,
+) -> io::Result<Option<Exit>> {
+    wait(child, dur, None, fds)
+}
+
+fn wait(
+    child: &mut Child,
+    dur: Duration,
+    interrupt: Option<&Interrupt>,
+    readable: [RawFd; 2],
+) -> io::Result<Option<Exit>> {
+    // Children have often exited by the time they're waited on, so check for
+    // that before setting anything up. The check for a previous reap doesn't
+    // reap anything itself, so a child which is still running is untouched.
+    let pid = child.id();
+    let reaped = if already_reaped(pid) {
+        Reaped::Cached
+    } else {
+        Reaped::Now
+    };
+    if let Some(exit) = try_wait(child, reaped).map_err(|e| not_our_child(e, pid))? {
+        trace!("child {} had already exited: {}", pid, exit.status);
+        return Ok(Some(exit));
+    }
+    // Nothing is left to wait for, such as after `Waiter::wait_timeout` spent
+    // the whole timeout spinning, so don't set up a blocking wait.
+    if dur == Duration::ZERO {
+        return Ok(None);
+    }
+
+    match backend() {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Backend::Pidfd => wait_pidfd(child, dur, interrupt, readable),
+        Backend::Sigchld => {
+            init(&Options::default())?;
+            unsafe { (*STATE).wait_timeout(child, dur, interrupt, readable) }
+        }
+    }
+}
+
+// Returns whether reading from `fd` wouldn't block, including at end of file.
+pub fn readable(fd: RawFd) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        match unsafe { libc::poll(&mut pollfd, 1, 0) } {
+            n if n >= 0 => return Ok(pollfd.revents != 0),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+// Returns how many bytes can be read from the child's stdout and stderr
+// without blocking.
+pub fn pending_bytes(child: &Child) -> io::Result<usize> {
+    let stdout = child.stdout.as_ref().map(|p| p.as_raw_fd());
+    let stderr = child.stderr.as_ref().map(|p| p.as_raw_fd());
+    let mut total = 0;
+    for fd in stdout.into_iter().chain(stderr) {
+        total += available(fd)?;
+    }
+    Ok(total)
+}
+
+// Returns how many bytes can be read from `fd` without blocking.
+pub fn available(fd: RawFd) -> io::Result<usize> {
+    let mut n: c_int = 0;
+    if unsafe { libc::ioctl(fd, libc::FIONREAD, &mut n) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(n as usize)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Backend {
+    Sigchld,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Pidfd,
+}
+
+// Picks the implementation used to wait on children, once per process.
+//
+// For debugging, `RUST_WAIT_TIMEOUT_BACKEND` can be set to `sigchld` or
+// `pidfd` to force one, as long as it's supported.
+fn backend() -> Backend {
+    static BACKEND: OnceLock<Backend> = OnceLock::new();
+    *BACKEND.get_or_init(|| {
+        let default = default_backend();
+        let backend = match env::var("RUST_WAIT_TIMEOUT_BACKEND") {
+            Err(_) => default,
+            Ok(ref name) if name == "sigchld" => Backend::Sigchld,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Ok(ref name) if name == "pidfd" && pidfd_supported() => Backend::Pidfd,
+            Ok(name) => {
+                warn!("unsupported wait backend {:?}, using {:?}", name, default);
+                default
+            }
+        };
+        debug!("waiting on children with the {:?} backend", backend);
+        backend
+    })
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn default_backend() -> Backend {
+    if pidfd_supported() {
+        Backend::Pidfd
+    } else {
+        Backend::Sigchld
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn default_backend() -> Backend {
+    Backend::Sigchld
+}
+
+// Returns whether the running kernel supports pidfds, which it does since
+// Linux 5.3. A seccomp filter may still reject the syscall, which is fine as
+// long as it fails with an error, but see `pidfd_allowed` for Android.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn pidfd_supported() -> bool {
+    let supported = pidfd_allowed() && pidfd_open(std::process::id()).is_ok();
+    debug!("pidfd support: {}", supported);
+    supported
+}
+
+#[cfg(target_os = "linux")]
+fn pidfd_allowed() -> bool {
+    true
+}
+
+// Android's seccomp policy only allows `pidfd_open` from API level 31 on, and
+// before that it kills the process with `SIGSYS` instead of failing the call,
+// so the syscall can't even be tried on older releases.
+#[cfg(target_os = "android")]
+fn pidfd_allowed() -> bool {
+    use std::ffi::CStr;
+
+    let mut value = [0 as libc::c_char; libc::PROP_VALUE_MAX as usize];
+    let name = b"ro.build.version.sdk\0";
+    let level = unsafe {
+        libc::__system_property_get(name.as_ptr() as *const libc::c_char, value.as_mut_ptr());
+        CStr::from_ptr(value.as_ptr())
+    };
+    let level = level
+        .to_str()
+        .ok()
+        .and_then(|l| l.parse::<u32>().ok())
+        .unwrap_or(0);
+    debug!("android api level: {}", level);
+    level >= 31
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn pidfd_open(pid: u32) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+    }
+}
+
+// Waits for `child` by polling a pidfd referring to it, which becomes readable
+// once the child exits. Unlike the signal based implementation this touches no
+// process-wide state at all.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn wait_pidfd(
+    child: &mut Child,
+    dur: Duration,
+    interrupt: Option<&Interrupt>,
+    readable: [RawFd; 2],
+) -> io::Result<Option<Exit>> {
+    let pid = child.id();
+    trace!("starting pidfd wait on child {} for {:?}", pid, dur);
+
+    // The child was still running when `wait` checked on it, so whatever
+    // status we find now is ours to reap. There's no race with another reap
+    // either, since this takes `&mut Child`.
+    let pidfd = pidfd_open(pid)?;
+
+    let start = Instant::now();
+    if !poll_pidfd(pidfd.as_raw_fd(), dur, interrupt, readable)? {
+        trace!("stopped waiting on child {} before it exited", pid);
+        return Ok(None);
+    }
+
+    let ret = match try_wait(child, Reaped::Now) {
+        Ok(ret) => ret,
+        // Reaped by an external reaper which hasn't recorded the status yet.
+        Err(ref e) if e.raw_os_error() == Some(libc::ECHILD) => {
+            wait_recorded(pid, dur.saturating_sub(start.elapsed()))
+                .map(|s| Exit::new(s, Reaped::Cached))
+        }
+        Err(e) => return Err(e),
+    };
+    if let Some(ref exit) = ret {
+        trace!("reaped child {}: {}", pid, exit.status);
+    }
+    Ok(ret)
+}
+
+// Polls `pidfd` until it becomes readable, which means the process exited, or
+// until either `dur` elapses, `interrupt` is set or one of `readable` becomes
+// readable. Returns whether the process exited.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn poll_pidfd(
+    pidfd: RawFd,
+    dur: Duration,
+    interrupt: Option<&Interrupt>,
+    readable: [RawFd; 2],
+) -> io::Result<bool> {
+    let start = Instant::now();
+    let mut fds = [
+        libc::pollfd {
+            fd: pidfd,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: interrupt.map_or(-1, |i| i.read.as_raw_fd()),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: readable[0],
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: readable[1],
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    loop {
+        let elapsed = start.elapsed();
+        if elapsed >= dur || interrupt.is_some_and(|i| i.is_interrupted()) {
+            return Ok(false);
+        }
+        let r = unsafe { libc::poll(fds.as_mut_ptr(), 4, poll_timeout(dur - elapsed)) };
+        if r < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if fds[0].revents != 0 {
+            return Ok(true);
+        }
+        if fds[2].revents != 0 || fds[3].revents != 0 {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn wait_pidfd_timeout(pidfd: RawFd, dur: Duration) -> io::Result<Option<ExitStatus>> {
+    if !poll_pidfd(pidfd, dur, None, [-1, -1])? {
+        return Ok(None);
+    }
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+    loop {
+        let r =
+            unsafe { libc::waitid(libc::P_PIDFD, pidfd as libc::id_t, &mut info, libc::WEXITED) };
+        if r == 0 {
+            return Ok(Some(status_from_siginfo(&info)));
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+// Converts the time left to wait into a timeout for `poll`, saturating rather
+// than overflowing for huge durations. Partial milliseconds are rounded up so
+// `poll` doesn't wake up early only for the caller to spin until `dur` is up.
+fn poll_timeout(dur: Duration) -> c_int {
+    let ms = dur
+        .as_secs()
+        .checked_mul(1_000)
+        .and_then(|amt| amt.checked_add((dur.subsec_nanos() as u64).div_ceil(1_000_000)))
+        .unwrap_or(u64::MAX);
+    cmp::min(c_int::MAX as u64, ms) as c_int
+}
+
+/// A flag which, once set, wakes up all waits that are watching it.
+pub struct Interrupt {
+    flag: AtomicBool,
+    read: UnixStream,
+    write: UnixStream,
+}
+
+impl Interrupt {
+    pub fn new() -> io::Result<Interrupt> {
+        let (read, write) = UnixStream::pair()?;
+        read.set_nonblocking(true)?;
+        write.set_nonblocking(true)?;
+        Ok(Interrupt {
+            flag: AtomicBool::new(false),
+            read,
+            write,
+        })
+    }
+
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+        // The pipe is never drained, so it stays readable and wakes up every
+        // wait watching this interrupt, both current and future ones.
+        notify(&self.write);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+}
+
+/// A set of watched children along with a descriptor which becomes readable
+/// when a child exits.
+#[derive(Debug)]
+pub struct Waiter {
+    notify: Notify,
+    pids: Mutex<HashMap<libc::pid_t, Watched>>,
+}
+
+#[derive(Debug)]
+enum Notify {
+    // A pipe written to by the signal handler whenever any child exits,
+    // through its slot in `WAITERS`.
+    Handler {
+        read: UnixStream,
+        _write: UnixStream,
+        slot: usize,
+    },
+    // An epoll instance holding a pidfd for each watched child, so only their
+    // exits make it readable, and no signal handler is needed.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Pidfd { epoll: OwnedFd },
+}
+
+#[derive(Debug)]
+struct Watched {
+    priority: i32,
+    // Registered with the epoll instance for as long as it's open.
+    _pidfd: Option<OwnedFd>,
+}
+
+// Returns whether waiters created with `options` are notified through pidfds,
+// which is the case whenever the pidfd backend is in use, unless a signal was
+// explicitly asked for.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn uses_pidfds(options: &Options) -> bool {
+    options.child_signal.is_none() && matches!(backend(), Backend::Pidfd)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn uses_pidfds(_options: &Options) -> bool {
+    false
+}
+
+impl Notify {
+    fn new(pidfds: bool) -> io::Result<Notify> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            if pidfds {
+                let epoll = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+                if epoll < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let epoll = unsafe { OwnedFd::from_raw_fd(epoll) };
+                return Ok(Notify::Pidfd { epoll });
+            }
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let _ = pidfds;
+
+        let (read, write) = UnixStream::pair()?;
+        read.set_nonblocking(true)?;
+        write.set_nonblocking(true)?;
+        let fd = write.as_raw_fd();
+        let slot = WAITERS
+            .iter()
+            .position(|slot| {
+                slot.compare_exchange(-1, fd, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            })
+            .ok_or_else(|| {
+                io::Error::other(format!(
+                    "too many live `Waiter`s, at most {} are supported",
+                    MAX_WAITERS
+                ))
+            })?;
+        Ok(Notify::Handler {
+            read,
+            _write: write,
+            slot,
+        })
+    }
+
+    fn uses_pidfds(&self) -> bool {
+        self.slot().is_none()
+    }
 
-pub fn wait_timeout(child: &mut Child, dur: Duration) -> io::Result<Option<ExitStatus>> {
-    INIT.call_once(State::init);
-    unsafe { (*STATE).wait_timeout(child, dur) }
+    fn slot(&self) -> Option<usize> {
+        match *self {
+            Notify::Handler { slot, .. } => Some(slot),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Notify::Pidfd { .. } => None,
+        }
+    }
+}
+
+impl Waiter {
+    pub fn new(capacity: usize, options: &Options) -> io::Result<Waiter> {
+        let pidfds = uses_pidfds(options);
+        if !pidfds {
+            init(options)?;
+        }
+        Waiter::with_notify(capacity, pidfds)
+    }
+
+    pub fn try_clone(&self) -> io::Result<Waiter> {
+        Waiter::with_notify(0, self.notify.uses_pidfds())
+    }
+
+    fn with_notify(capacity: usize, pidfds: bool) -> io::Result<Waiter> {
+        let mut pids = HashMap::new();
+        pids.try_reserve(capacity).map_err(out_of_memory)?;
+        Ok(Waiter {
+            notify: Notify::new(pidfds)?,
+            pids: Mutex::new(pids),
+        })
+    }
+
+    pub fn watch(&self, child: &Child, priority: i32) {
+        let pid = child.id() as libc::pid_t;
+        let mut pids = self.lock();
+        if let Some(watched) = pids.get_mut(&pid) {
+            watched.priority = priority;
+            return;
+        }
+        let pidfd = match self.notify {
+            Notify::Handler { .. } => None,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Notify::Pidfd { ref epoll } => match register(epoll.as_raw_fd(), pid) {
+                Ok(pidfd) => Some(pidfd),
+                // Its exit is still reported by `drain_exits`, but doesn't
+                // make our descriptor readable.
+                Err(e) => {
+                    warn!("failed to watch child {} through a pidfd: {}", pid, e);
+                    None
+                }
+            },
+        };
+        pids.insert(
+            pid,
+            Watched {
+                priority,
+                _pidfd: pidfd,
+            },
+        );
+    }
+
+    pub fn contains(&self, pid: u32) -> bool {
+        self.lock().contains_key(&(pid as libc::pid_t))
+    }
+
+    pub fn remove(&self, pid: u32) -> io::Result<()> {
+        match self.lock().remove(&(pid as libc::pid_t)) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("child {} is not watched by this `Waiter`", pid),
+            )),
+        }
+    }
+
+    pub fn drain_exits(&self) -> io::Result<Vec<(u32, ExitStatus)>> {
+        // The pidfds of children harvested below are closed as they're
+        // removed, which is all it takes to make an epoll instance no longer
+        // readable.
+        match self.notify {
+            Notify::Handler { ref read, .. } => {
+                drain(read);
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Notify::Pidfd { .. } => {}
+        }
+
+        // Peek at each watched child without reaping it, so the owner of the
+        // `Child` can still collect its status as usual.
+        let mut pids = self.lock();
+        let mut ret = Vec::new();
+        let mut gone = Vec::new();
+        for (&pid, watched) in pids.iter() {
+            let priority = watched.priority;
+            match peek(pid as u32) {
+                Ok(Some(status)) => {
+                    trace!("harvested exit of child {}", pid);
+                    ret.push((priority, pid as u32, status));
+                    gone.push(pid);
+                }
+                Ok(None) => {}
+                // Already reaped elsewhere, so there's nothing to report.
+                Err(ref e) if e.raw_os_error() == Some(libc::ECHILD) => gone.push(pid),
+                Err(e) => return Err(e),
+            }
+        }
+        for pid in gone {
+            pids.remove(&pid);
+        }
+        ret.sort_by_key(|&(priority, ..)| cmp::Reverse(priority));
+        Ok(ret
+            .into_iter()
+            .map(|(_, pid, status)| (pid, status))
+            .collect())
+    }
+
+    pub fn poll_all(&self, dur: Duration) -> io::Result<Vec<(u32, ExitStatus)>> {
+        let start = Instant::now();
+        loop {
+            // Our pipe is drained before looking for exits, so an exit we miss
+            // leaves it readable and ends the `poll` below right away.
+            let exits = self.drain_exits()?;
+            let elapsed = start.elapsed();
+            if !exits.is_empty() || elapsed >= dur {
+                return Ok(exits);
+            }
+            let mut fd = libc::pollfd {
+                fd: self.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut fd, 1, poll_timeout(dur - elapsed)) } < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn reap_orphans(
+        &self,
+        known: &mut dyn FnMut(u32) -> bool,
+    ) -> io::Result<Vec<(u32, ExitStatus)>> {
+        let me = unsafe { libc::getpid() };
+        let watched = self.lock();
+        let mut ret = Vec::new();
+        for (pid, ppid) in processes()? {
+            if ppid != me || watched.contains_key(&pid) || known(pid as u32) {
+                continue;
+            }
+            // Only reap children which have already exited, without waiting on
+            // the ones still running.
+            let mut status = 0;
+            match unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } {
+                0 => {}
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    // Reaped by someone else since we listed it.
+                    if err.raw_os_error() != Some(libc::ECHILD) {
+                        return Err(err);
+                    }
+                }
+                _ => {
+                    let status = ExitStatus::from_raw(status);
+                    trace!("reaped orphan {}: {}", pid, status);
+                    ret.push((pid as u32, status));
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<libc::pid_t, Watched>> {
+        self.pids.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Opens a pidfd for `pid` and adds it to `epoll`, which it makes readable once
+// the child exits.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn register(epoll: RawFd, pid: libc::pid_t) -> io::Result<OwnedFd> {
+    let pidfd = pidfd_open(pid as u32)?;
+    let mut event = libc::epoll_event {
+        events: libc::EPOLLIN as u32,
+        u64: pid as u64,
+    };
+    let r = unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_ADD, pidfd.as_raw_fd(), &mut event) };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(pidfd)
+}
+
+impl AsRawFd for Waiter {
+    fn as_raw_fd(&self) -> RawFd {
+        match self.notify {
+            Notify::Handler { ref read, .. } => read.as_raw_fd(),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Notify::Pidfd { ref epoll } => epoll.as_raw_fd(),
+        }
+    }
+}
+
+impl Drop for Notify {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot() {
+            WAITERS[slot].store(-1, Ordering::SeqCst);
+            // Our pipe is closed when the fields are dropped right after this.
+            while HANDLERS.load(Ordering::SeqCst) > 0 {
+                std::thread::yield_now();
+            }
+        }
+    }
+}
+
+// Builds the status `waitpid` would have reported from the `siginfo_t` filled
+// in by `waitid`.
+fn status_from_siginfo(info: &libc::siginfo_t) -> ExitStatus {
+    let status = unsafe { info.si_status() };
+    let raw = match info.si_code {
+        libc::CLD_EXITED => (status & 0xff) << 8,
+        libc::CLD_DUMPED => status | 0x80,
+        _ => status,
+    };
+    ExitStatus::from_raw(raw)
+}
+
+pub fn exited(status: &ExitStatus) -> bool {
+    libc::WIFEXITED(status.into_raw())
+}
+
+pub fn signaled(status: &ExitStatus) -> bool {
+    libc::WIFSIGNALED(status.into_raw())
+}
+
+pub fn stopped(status: &ExitStatus) -> bool {
+    libc::WIFSTOPPED(status.into_raw())
+}
+
+pub fn continued(status: &ExitStatus) -> bool {
+    libc::WIFCONTINUED(status.into_raw())
+}
+
+pub fn term_signal(status: &ExitStatus) -> Option<i32> {
+    if signaled(status) {
+        Some(libc::WTERMSIG(status.into_raw()))
+    } else {
+        None
+    }
+}
+
+pub fn raw_wait_status(status: &ExitStatus) -> Option<i32> {
+    Some(status.into_raw())
+}
+
+pub fn crashed(status: &ExitStatus) -> bool {
+    matches!(
+        term_signal(status),
+        Some(libc::SIGSEGV | libc::SIGABRT | libc::SIGBUS | libc::SIGILL | libc::SIGFPE)
+    )
+}
+
+pub fn stop_signal(status: &ExitStatus) -> Option<i32> {
+    if stopped(status) {
+        Some(libc::WSTOPSIG(status.into_raw()))
+    } else {
+        None
+    }
+}
+
+/// Sends `SIGKILL` to a child, or the process group it leads, from another
+/// thread.
+#[derive(Clone, Copy)]
+pub struct Killer {
+    pid: libc::pid_t,
+}
+
+impl Killer {
+    pub fn new(child: &Child) -> Killer {
+        Killer {
+            pid: child.id() as libc::pid_t,
+        }
+    }
+
+    pub fn process_group(child: &Child) -> Killer {
+        Killer {
+            pid: -(child.id() as libc::pid_t),
+        }
+    }
+
+    pub fn kill(&self) {
+        drop(self.kill_checked());
+    }
+
+    // A process group with nothing left in it has nothing left to kill, so
+    // that's not reported as an error.
+    pub fn kill_checked(&self) -> io::Result<()> {
+        if unsafe { libc::kill(self.pid, libc::SIGKILL) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if self.pid < 0 && err.raw_os_error() == Some(libc::ESRCH) {
+            return Ok(());
+        }
+        Err(err)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn kill_tree(child: &mut Child) -> io::Result<()> {
+    use std::collections::HashSet;
+
+    // Stop the whole tree before killing anything, so nothing in it can spawn
+    // new processes behind our back or get reparented away from us while
+    // we're walking it. Keep looking for descendants until a scan finds no
+    // new ones.
+    let root = child.id() as libc::pid_t;
+    let mut tree = vec![root];
+    let mut seen = HashSet::new();
+    seen.insert(root);
+    unsafe {
+        libc::kill(root, libc::SIGSTOP);
+    }
+    loop {
+        let mut found = false;
+        let procs = match processes() {
+            Ok(procs) => procs,
+            Err(e) => {
+                // don't leave whatever we've stopped so far hanging around
+                kill_all(&tree);
+                return Err(e);
+            }
+        };
+        for (pid, ppid) in procs {
+            if seen.contains(&ppid) && seen.insert(pid) {
+                unsafe {
+                    libc::kill(pid, libc::SIGSTOP);
+                }
+                tree.push(pid);
+                found = true;
+            }
+        }
+        if !found {
+            break;
+        }
+    }
+
+    trace!("killing process tree of {}: {:?}", root, tree);
+    kill_all(&tree);
+    Ok(())
+}
+
+// Kills every process in `tree`, leaves first.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn kill_all(tree: &[libc::pid_t]) {
+    for &pid in tree.iter().rev() {
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+        }
+    }
+}
+
+// Lists the identifier and parent identifier of every process in `/proc`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn processes() -> io::Result<Vec<(libc::pid_t, libc::pid_t)>> {
+    use std::fs;
+
+    let mut ret = Vec::new();
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // The process may have exited since we listed it, which is fine.
+        let stat = match fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        // The format is `pid (comm) state ppid ...`, where `comm` can contain
+        // spaces and parentheses, so parse from its closing parenthesis.
+        let ppid = stat
+            .rfind(')')
+            .and_then(|i| stat[i + 1..].split_whitespace().nth(1))
+            .and_then(|s| s.parse().ok());
+        if let Some(ppid) = ppid {
+            ret.push((pid, ppid));
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn kill_tree(child: &mut Child) -> io::Result<()> {
+    // Without a portable way to walk the process tree we fall back to the
+    // process group, which only covers descendants if the child leads it.
+    let pid = child.id() as libc::pid_t;
+    unsafe {
+        if libc::getpgid(pid) == pid {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+    Ok(())
 }
 
 impl State {
     #[allow(unused_assignments)]
-    fn init() {
+    // `read` and `write` are the ends of our nonblocking "self pipe".
+    fn init(options: &Options, read: UnixStream, write: UnixStream) {
         unsafe {
-            // Create our "self pipe" and then set both ends to nonblocking
-            // mode.
-            let (read, write) = UnixStream::pair().unwrap();
-            read.set_nonblocking(true).unwrap();
-            write.set_nonblocking(true).unwrap();
-
+            let signal = options.child_signal.unwrap_or(libc::SIGCHLD);
             let mut state = Box::new(State {
+                signal,
                 prev: mem::zeroed(),
-                write: write,
-                read: read,
+                write,
+                read,
                 map: Mutex::new(HashMap::new()),
             });
 
-            // Register our sigchld handler
+            // Register our sigchld handler. This is usually for `SIGCHLD`
+            // itself, but some sandboxes deliver a different signal when a
+            // child exits. The handler doesn't care which signal it's for.
             let mut new: libc::sigaction = mem::zeroed();
-            new.sa_sigaction = sigchld_handler as usize;
-            new.sa_flags = libc::SA_NOCLDSTOP | libc::SA_RESTART | libc::SA_SIGINFO;
-
-            assert_eq!(libc::sigaction(libc::SIGCHLD, &new, &mut state.prev), 0);
+            new.sa_sigaction = sigchld_handler as *const () as usize;
+            new.sa_flags = libc::SA_NOCLDSTOP | libc::SA_SIGINFO;
+            if options.restart {
+                new.sa_flags |= libc::SA_RESTART;
+            }
 
-            STATE = mem::transmute(state);
+            // The handler dereferences `STATE` as soon as it's installed, so
+            // publish it (along with the handler we're chaining to) first.
+            assert_eq!(libc::sigaction(signal, ptr::null(), &mut state.prev), 0);
+            STATE = Box::into_raw(state);
+            install(signal, &new);
+            debug!("installed child exit handler for signal {}", signal);
         }
     }
 
-    fn wait_timeout(&self, child: &mut Child, dur: Duration) -> io::Result<Option<ExitStatus>> {
+    fn wait_timeout(
+        &self,
+        child: &mut Child,
+        dur: Duration,
+        interrupt: Option<&Interrupt>,
+        readable: [RawFd; 2],
+    ) -> io::Result<Option<Exit>> {
         // First up, prep our notification pipe which will tell us when our
         // child has been reaped (other threads may signal this pipe).
         let (read, write) = UnixStream::pair()?;
         read.set_nonblocking(true)?;
         write.set_nonblocking(true)?;
 
+        let pid = child.id();
+        trace!("starting wait on child {} for {:?}", pid, dur);
+
         // Next, take a lock on the map of children currently waiting. Right
         // after this, **before** we add ourselves to the map, we check to see
         // if our child has actually already exited via a `try_wait`. If the
@@ -90,9 +1109,19 @@ impl State {
         // If the wait reports the child is still running, however, we add
         // ourselves to the map and then block in `select` waiting for something
         // to happen.
-        let mut map = self.map.lock().unwrap();
-        if let Some(status) = child.try_wait()? {
-            return Ok(Some(status));
+        //
+        // Note that `Child` caches the status once it's been reaped, so to
+        // tell our caller whether the status is fresh we first check whether
+        // the process is still around to be reaped at all.
+        let mut map = self.lock();
+        let reaped = if already_reaped(pid) {
+            Reaped::Cached
+        } else {
+            Reaped::Now
+        };
+        if let Some(exit) = try_wait(child, reaped).map_err(|e| not_our_child(e, pid))? {
+            trace!("child {} had already exited: {}", pid, exit.status);
+            return Ok(Some(exit));
         }
         assert!(map.insert(child, (write, None)).is_none());
         drop(map);
@@ -105,7 +1134,7 @@ impl State {
         }
         impl<'a> Drop for Remove<'a> {
             fn drop(&mut self) {
-                let mut map = self.state.map.lock().unwrap();
+                let mut map = self.state.lock();
                 drop(map.remove(&(self.child as *mut Child)));
             }
         }
@@ -132,35 +1161,51 @@ impl State {
                 events: libc::POLLIN,
                 revents: 0,
             },
+            // A negative fd is ignored by `poll`, so this slot is inert when
+            // we're not watching an interrupt.
+            libc::pollfd {
+                fd: interrupt.map_or(-1, |i| i.read.as_raw_fd()),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            // Likewise for the descriptors whose readability also ends the
+            // wait.
+            libc::pollfd {
+                fd: readable[0],
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: readable[1],
+                events: libc::POLLIN,
+                revents: 0,
+            },
         ];
         loop {
             let elapsed = start.elapsed();
             if elapsed >= dur {
                 break;
             }
-            let timeout = dur - elapsed;
-            let timeout = timeout
-                .as_secs()
-                .checked_mul(1_000)
-                .and_then(|amt| amt.checked_add(timeout.subsec_nanos() as u64 / 1_000_000))
-                .unwrap_or(u64::max_value());
-            let timeout = cmp::min(<c_int>::max_value() as u64, timeout) as c_int;
-            let r = unsafe { libc::poll(fds.as_mut_ptr(), 2, timeout) };
+            if interrupt.is_some_and(|i| i.is_interrupted()) {
+                trace!("wait on child {} interrupted", pid);
+                break;
+            }
+            let timeout = poll_timeout(dur - elapsed);
+            let r = unsafe { libc::poll(fds.as_mut_ptr(), 5, timeout) };
             let timeout = match r {
                 0 => true,
                 n if n > 0 => false,
-                n => {
+                _ => {
                     let err = io::Error::last_os_error();
                     if err.kind() == io::ErrorKind::Interrupted {
                         continue;
-                    } else {
-                        panic!("error in select = {}: {}", n, err)
                     }
+                    return Err(err);
                 }
             };
 
             // Now that something has happened, we need to process what actually
-            // happened. There's are three reasons we could have woken up:
+            // happened. There are five reasons we could have woken up:
             //
             // 1. The file descriptor in our SIGCHLD handler was written to.
             //    This means that a SIGCHLD was received and we need to poll the
@@ -171,42 +1216,185 @@ impl State {
             //    local map.
             // 3. We timed out. This means we need to remove ourselves from the
             //    map and simply carry on.
+            // 4. The interrupt we're watching, if any, was set. This is picked
+            //    up at the top of the loop.
+            // 5. A descriptor our caller is waiting to read from, if any,
+            //    became readable. This ends the wait just like a timeout.
             //
             // In the case that a SIGCHLD signal was received, we do that
             // processing and keep going. If our fd was written to or a timeout
             // was received then we break out of the loop and return from this
             // call.
-            let mut map = self.map.lock().unwrap();
+            let mut map = self.lock();
             if drain(&self.read) {
-                self.process_sigchlds(&mut map);
+                trace!("SIGCHLD received while waiting on child {}", pid);
+                self.process_sigchlds(&mut map, remove.child);
             }
 
-            if drain(&read) || timeout {
+            if drain(&read) {
+                break;
+            }
+            if timeout {
+                trace!("timed out waiting on child {}", pid);
+                break;
+            }
+            if fds[3].revents != 0 || fds[4].revents != 0 {
+                trace!("stopped waiting on child {} to read from it", pid);
                 break;
             }
+            trace!("spurious wakeup while waiting on child {}", pid);
         }
 
-        let mut map = self.map.lock().unwrap();
+        let mut map = self.lock();
         let (_write, ret) = map.remove(&(remove.child as *mut Child)).unwrap();
         drop(map);
         Ok(ret)
     }
 
-    fn process_sigchlds(&self, map: &mut StateMap) {
+    // Acquires the lock on the map of waiting children.
+    //
+    // If another thread panicked while holding the lock we recover the map
+    // rather than propagating the poison. Each entry is inserted and removed
+    // as a whole, so the map is always consistent, and otherwise a single bad
+    // wait would break every future wait in the process.
+    fn lock(&self) -> MutexGuard<'_, StateMap> {
+        self.map.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Reaps every child in `map` which has exited, where `me` is the child
+    // that the calling thread is itself waiting on. Statuses reaped for other
+    // threads are recorded as cached for them.
+    fn process_sigchlds(&self, map: &mut StateMap, me: *mut Child) {
         for (&k, &mut (ref write, ref mut status)) in map {
             // Already reaped, nothing to do here
             if status.is_some() {
                 continue;
             }
 
-            *status = unsafe { (*k).try_wait().unwrap() };
-            if status.is_some() {
+            let reaped = if k == me { Reaped::Now } else { Reaped::Cached };
+            // An error means the child was reaped by an external reaper which
+            // hasn't recorded its status yet, which wakes us up again once it
+            // does.
+            *status = unsafe { try_wait(&mut *k, reaped) }.unwrap_or(None);
+            if let Some(ref exit) = *status {
+                trace!("reaped child {}: {}", unsafe { (*k).id() }, exit.status);
                 notify(write);
             }
         }
     }
 }
 
+// Same as `Child::try_wait`, except that the status of a child reaped by an
+// external reaper is taken from those recorded through `record_exit`.
+fn try_wait(child: &mut Child, reaped: Reaped) -> io::Result<Option<Exit>> {
+    match child.try_wait() {
+        Ok(status) => Ok(status.map(|s| Exit::new(s, reaped))),
+        Err(e) => {
+            if e.raw_os_error() == Some(libc::ECHILD) {
+                if let Some(status) = take_recorded(child.id()) {
+                    return Ok(Some(Exit::new(status, Reaped::Cached)));
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+// Replaces the opaque `ECHILD` from waiting on a process which isn't a child of
+// ours, or was already reaped elsewhere, with an error saying as much.
+pub fn not_our_child(err: io::Error, pid: u32) -> io::Error {
+    if err.raw_os_error() != Some(libc::ECHILD) {
+        return err;
+    }
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "process {} is not a child of this process, or was already \
+             reaped without its status being recorded",
+            pid
+        ),
+    )
+}
+
+// Statuses of children reaped outside of this crate, as recorded by
+// `record_exit`, which are removed as they're handed out.
+static RECORDED: Mutex<Vec<(u32, ExitStatus)>> = Mutex::new(Vec::new());
+static RECORDED_CHANGED: Condvar = Condvar::new();
+
+pub fn record_exit(pid: u32, status: ExitStatus) {
+    let mut recorded = RECORDED.lock().unwrap_or_else(|e| e.into_inner());
+    recorded.retain(|&(p, _)| p != pid);
+    recorded.push((pid, status));
+    drop(recorded);
+    trace!("recorded exit of child {}: {}", pid, status);
+
+    // Wake up everyone who may be waiting for this child.
+    RECORDED_CHANGED.notify_all();
+    if INIT.is_completed() {
+        unsafe {
+            notify(&(*STATE).write);
+        }
+    }
+}
+
+fn take_recorded(pid: u32) -> Option<ExitStatus> {
+    let mut recorded = RECORDED.lock().unwrap_or_else(|e| e.into_inner());
+    let i = recorded.iter().position(|&(p, _)| p == pid)?;
+    Some(recorded.remove(i).1)
+}
+
+// Waits for up to `dur` for the status of `pid` to be recorded.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn wait_recorded(pid: u32, dur: Duration) -> Option<ExitStatus> {
+    let start = Instant::now();
+    let mut recorded = RECORDED.lock().unwrap_or_else(|e| e.into_inner());
+    loop {
+        if let Some(i) = recorded.iter().position(|&(p, _)| p == pid) {
+            return Some(recorded.remove(i).1);
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= dur {
+            return None;
+        }
+        recorded = RECORDED_CHANGED
+            .wait_timeout(recorded, dur - elapsed)
+            .unwrap_or_else(|e| e.into_inner())
+            .0;
+    }
+}
+
+// Returns whether the child `pid` has already been reaped, without reaping it
+// if it hasn't.
+fn already_reaped(pid: u32) -> bool {
+    match peek(pid) {
+        Err(e) => e.raw_os_error() == Some(libc::ECHILD),
+        Ok(_) => false,
+    }
+}
+
+// Returns the status of the child `pid` if it has exited, leaving it to be
+// reaped by whoever waits on it next.
+pub fn peek(pid: u32) -> io::Result<Option<ExitStatus>> {
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+    let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+    loop {
+        let r = unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, flags) };
+        if r == 0 {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    // With `WNOHANG` a zeroed `si_pid` means the child is still running.
+    if unsafe { info.si_pid() } == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(status_from_siginfo(&info)))
+    }
+}
+
 fn drain(mut file: &UnixStream) -> bool {
     let mut ret = false;
     let mut buf = [0u8; 16];
@@ -226,7 +1414,7 @@ fn drain(mut file: &UnixStream) -> bool {
 }
 
 fn notify(mut file: &UnixStream) {
-    match file.write(&[1]) {
+    match file.write_all(&[1]) {
         Ok(..) => {}
         Err(e) => {
             if e.kind() != io::ErrorKind::WouldBlock {
@@ -253,8 +1441,16 @@ extern "C" fn sigchld_handler(signum: c_int, info: *mut libc::siginfo_t, ptr: *m
     type FnHandler = extern "C" fn(c_int);
 
     unsafe {
+        HANDLERS.fetch_add(1, Ordering::SeqCst);
         let state = &*STATE;
         notify(&state.write);
+        for slot in WAITERS.iter() {
+            let fd = slot.load(Ordering::SeqCst);
+            if fd >= 0 {
+                libc::write(fd, [1u8].as_ptr() as *const libc::c_void, 1);
+            }
+        }
+        HANDLERS.fetch_sub(1, Ordering::SeqCst);
 
         let fnptr = state.prev.sa_sigaction;
         if fnptr == 0 {
@@ -269,3 +1465,66 @@ extern "C" fn sigchld_handler(signum: c_int, info: *mut libc::siginfo_t, ptr: *m
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raise() {
+        unsafe {
+            assert_eq!(libc::raise(libc::SIGCHLD), 0);
+        }
+    }
+
+    // Every test initializes through here, so whichever runs first raises a
+    // signal in the middle of initialization.
+    fn init_raising() -> &'static State {
+        *INIT_PAUSE.lock().unwrap() = Some(raise);
+        init(&Options::default()).unwrap();
+        unsafe { &*STATE }
+    }
+
+    // A child exit signal delivered as soon as the handler is installed used
+    // to find `STATE` still null, which aborts the test binary.
+    #[test]
+    fn signal_during_init() {
+        init_raising();
+        assert!(INIT.is_completed());
+    }
+
+    #[test]
+    fn waiter_teardown() {
+        use std::process::Command;
+        use std::thread;
+
+        let state = init_raising();
+        let mut child = Command::new("sleep").arg("1000").spawn().unwrap();
+        let key = &mut child as *mut Child;
+        let waiter = Waiter::with_notify(0, false).unwrap();
+        waiter.watch(&child, 0);
+        let slot = waiter.notify.slot().unwrap();
+
+        thread::scope(|s| {
+            let waiting = s.spawn(|| {
+                // `State` isn't `Sync`, it's shared through `STATE` instead
+                let state = unsafe { &*STATE };
+                let ret =
+                    state.wait_timeout(&mut child, Duration::from_millis(500), None, [-1, -1]);
+                drop(waiter);
+                ret
+            });
+            // the wait has an entry in the map while it's blocked
+            let start = Instant::now();
+            while !state.lock().contains_key(&key) {
+                assert!(start.elapsed() < Duration::from_secs(10));
+                thread::yield_now();
+            }
+            assert!(waiting.join().unwrap().unwrap().is_none());
+        });
+
+        assert!(state.lock().is_empty());
+        assert_eq!(WAITERS[slot].load(Ordering::SeqCst), -1);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
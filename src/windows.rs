@@ -1,31 +1,410 @@
+#![allow(bad_style)]
+
 use std::io;
+use std::mem;
 use std::os::windows::prelude::*;
+use std::os::windows::process::ExitStatusExt;
 use std::process::{Child, ExitStatus};
-use std::time::Duration;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use {Exit, Reaped};
 
+type BOOL = i32;
 type DWORD = u32;
 type HANDLE = *mut u8;
 
-const WAIT_OBJECT_0: DWORD = 0x00000000;
+const FALSE: BOOL = 0;
+const INVALID_HANDLE_VALUE: HANDLE = !0 as HANDLE;
+const MAX_PATH: usize = 260;
+const PROCESS_TERMINATE: DWORD = 0x0001;
+const TH32CS_SNAPPROCESS: DWORD = 0x00000002;
+const TRUE: BOOL = 1;
 const WAIT_TIMEOUT: DWORD = 258;
+const JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE: DWORD = 0x00002000;
+const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION_CLASS: i32 = 9;
+
+#[repr(C)]
+struct PROCESSENTRY32W {
+    dwSize: DWORD,
+    cntUsage: DWORD,
+    th32ProcessID: DWORD,
+    th32DefaultHeapID: usize,
+    th32ModuleID: DWORD,
+    cntThreads: DWORD,
+    th32ParentProcessID: DWORD,
+    pcPriClassBase: i32,
+    dwFlags: DWORD,
+    szExeFile: [u16; MAX_PATH],
+}
+
+#[repr(C)]
+struct JOBOBJECT_BASIC_LIMIT_INFORMATION {
+    PerProcessUserTimeLimit: i64,
+    PerJobUserTimeLimit: i64,
+    LimitFlags: DWORD,
+    MinimumWorkingSetSize: usize,
+    MaximumWorkingSetSize: usize,
+    ActiveProcessLimit: DWORD,
+    Affinity: usize,
+    PriorityClass: DWORD,
+    SchedulingClass: DWORD,
+}
+
+#[repr(C)]
+struct IO_COUNTERS {
+    ReadOperationCount: u64,
+    WriteOperationCount: u64,
+    OtherOperationCount: u64,
+    ReadTransferCount: u64,
+    WriteTransferCount: u64,
+    OtherTransferCount: u64,
+}
+
+#[repr(C)]
+struct JOBOBJECT_EXTENDED_LIMIT_INFORMATION {
+    BasicLimitInformation: JOBOBJECT_BASIC_LIMIT_INFORMATION,
+    IoInfo: IO_COUNTERS,
+    ProcessMemoryLimit: usize,
+    JobMemoryLimit: usize,
+    PeakProcessMemoryUsed: usize,
+    PeakJobMemoryUsed: usize,
+}
 
 extern "system" {
-    fn WaitForSingleObject(hHandle: HANDLE, dwMilliseconds: DWORD) -> DWORD;
+    fn WaitForMultipleObjects(
+        nCount: DWORD,
+        lpHandles: *const HANDLE,
+        bWaitAll: BOOL,
+        dwMilliseconds: DWORD,
+    ) -> DWORD;
+    fn CreateEventW(
+        lpEventAttributes: *mut u8,
+        bManualReset: BOOL,
+        bInitialState: BOOL,
+        lpName: *const u16,
+    ) -> HANDLE;
+    fn SetEvent(hEvent: HANDLE) -> BOOL;
+    fn TerminateProcess(hProcess: HANDLE, uExitCode: u32) -> BOOL;
+    fn GetExitCodeProcess(hProcess: HANDLE, lpExitCode: *mut DWORD) -> BOOL;
+    fn CloseHandle(hObject: HANDLE) -> BOOL;
+    fn CreateJobObjectW(lpJobAttributes: *mut u8, lpName: *const u16) -> HANDLE;
+    fn SetInformationJobObject(
+        hJob: HANDLE,
+        JobObjectInformationClass: i32,
+        lpJobObjectInformation: *mut u8,
+        cbJobObjectInformationLength: DWORD,
+    ) -> BOOL;
+    fn AssignProcessToJobObject(hJob: HANDLE, hProcess: HANDLE) -> BOOL;
+    fn TerminateJobObject(hJob: HANDLE, uExitCode: u32) -> BOOL;
+    fn CreateToolhelp32Snapshot(dwFlags: DWORD, th32ProcessID: DWORD) -> HANDLE;
+    fn Process32FirstW(hSnapshot: HANDLE, lppe: *mut PROCESSENTRY32W) -> BOOL;
+    fn Process32NextW(hSnapshot: HANDLE, lppe: *mut PROCESSENTRY32W) -> BOOL;
+    fn OpenProcess(dwDesiredAccess: DWORD, bInheritHandle: BOOL, dwProcessId: DWORD) -> HANDLE;
+    fn PeekNamedPipe(
+        hNamedPipe: HANDLE,
+        lpBuffer: *mut u8,
+        nBufferSize: DWORD,
+        lpBytesRead: *mut DWORD,
+        lpTotalBytesAvail: *mut DWORD,
+        lpBytesLeftThisMessage: *mut DWORD,
+    ) -> BOOL;
+}
+
+/// An owned Job Object configured to kill its processes when closed.
+pub struct Job {
+    handle: HANDLE,
+}
+
+unsafe impl Send for Job {}
+unsafe impl Sync for Job {}
+
+impl Job {
+    pub fn new() -> io::Result<Job> {
+        unsafe {
+            let handle = CreateJobObjectW(ptr::null_mut(), ptr::null());
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let job = Job { handle };
+
+            let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = mem::zeroed();
+            info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            let r = SetInformationJobObject(
+                job.handle,
+                JOB_OBJECT_EXTENDED_LIMIT_INFORMATION_CLASS,
+                &mut info as *mut _ as *mut u8,
+                mem::size_of_val(&info) as DWORD,
+            );
+            if r == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(job)
+        }
+    }
+
+    pub fn assign(&self, child: &Child) -> io::Result<()> {
+        unsafe {
+            if AssignProcessToJobObject(self.handle, child.as_raw_handle() as HANDLE) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn kill(&self) -> io::Result<()> {
+        unsafe {
+            if TerminateJobObject(self.handle, 1) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.handle);
+        }
+    }
+}
+
+/// A flag which, once set, wakes up all waits that are watching it.
+pub struct Interrupt {
+    flag: AtomicBool,
+    // A manual-reset event which is never reset, so it wakes up every wait
+    // watching this interrupt, both current and future ones.
+    event: HANDLE,
+}
+
+unsafe impl Send for Interrupt {}
+unsafe impl Sync for Interrupt {}
+
+impl Interrupt {
+    pub fn new() -> io::Result<Interrupt> {
+        let event = unsafe { CreateEventW(ptr::null_mut(), TRUE, FALSE, ptr::null()) };
+        if event.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Interrupt {
+            flag: AtomicBool::new(false),
+            event,
+        })
+    }
+
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+        unsafe {
+            SetEvent(self.event);
+        }
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Interrupt {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.event);
+        }
+    }
+}
+
+/// Terminates a child from another thread.
+#[derive(Clone, Copy)]
+pub struct Killer {
+    // Stored as an integer so the killer is `Send`, the handle stays owned by
+    // the `Child` which outlives this.
+    handle: usize,
+}
+
+impl Killer {
+    pub fn new(child: &Child) -> Killer {
+        Killer {
+            handle: child.as_raw_handle() as usize,
+        }
+    }
+
+    pub fn kill(&self) {
+        unsafe {
+            TerminateProcess(self.handle as HANDLE, 1);
+        }
+    }
+}
+
+pub fn wait_timeout(
+    child: &mut Child,
+    dur: Duration,
+    interrupt: Option<&Interrupt>,
+) -> io::Result<Option<Exit>> {
+    let mut handles = vec![child.as_raw_handle() as HANDLE];
+    if let Some(interrupt) = interrupt {
+        if interrupt.is_interrupted() {
+            return Ok(None);
+        }
+        handles.push(interrupt.event);
+    }
+    if wait_objects(&handles, dur)? != Some(0) {
+        return Ok(None);
+    }
+    // Processes aren't reaped on Windows, the status is always read afresh
+    // from the handle.
+    Ok(child.try_wait()?.map(|s| Exit::new(s, Reaped::Now)))
+}
+
+// Returns how many bytes can be read from the child's stdout and stderr
+// without blocking.
+pub fn pending_bytes(child: &Child) -> io::Result<usize> {
+    let stdout = child.stdout.as_ref().map(|p| p.as_raw_handle());
+    let stderr = child.stderr.as_ref().map(|p| p.as_raw_handle());
+    let mut total = 0;
+    for handle in stdout.into_iter().chain(stderr) {
+        let mut n: DWORD = 0;
+        let ok = unsafe {
+            PeekNamedPipe(
+                handle as HANDLE,
+                ptr::null_mut(),
+                0,
+                ptr::null_mut(),
+                &mut n,
+                ptr::null_mut(),
+            )
+        };
+        if ok == FALSE {
+            return Err(io::Error::last_os_error());
+        }
+        total += n as usize;
+    }
+    Ok(total)
+}
+
+pub fn wait_handle_timeout(handle: RawHandle, dur: Duration) -> io::Result<Option<ExitStatus>> {
+    if wait_objects(&[handle as HANDLE], dur)?.is_none() {
+        return Ok(None);
+    }
+    let mut code = 0;
+    unsafe {
+        if GetExitCodeProcess(handle as HANDLE, &mut code) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(Some(ExitStatus::from_raw(code)))
+}
+
+pub fn kill_tree(child: &mut Child) -> io::Result<()> {
+    let processes = processes()?;
+
+    // Collect the descendants breadth first, then terminate them.
+    let mut tree = vec![child.id()];
+    let mut i = 0;
+    while i < tree.len() {
+        let parent = tree[i];
+        for &(pid, ppid) in processes.iter() {
+            if ppid == parent && pid != parent && !tree.contains(&pid) {
+                tree.push(pid);
+            }
+        }
+        i += 1;
+    }
+    for &pid in tree[1..].iter() {
+        unsafe {
+            let handle = OpenProcess(PROCESS_TERMINATE, FALSE, pid);
+            if !handle.is_null() {
+                TerminateProcess(handle, 1);
+                CloseHandle(handle);
+            }
+        }
+    }
+    Ok(())
 }
 
-pub fn wait_timeout(child: &mut Child, dur: Duration) -> io::Result<Option<ExitStatus>> {
-    let ms = dur.as_millis();
-    let ms = if ms > (DWORD::max_value() as u128) {
-        DWORD::max_value()
-    } else {
-        ms as DWORD
-    };
+// Lists the identifier and parent identifier of every running process.
+fn processes() -> io::Result<Vec<(DWORD, DWORD)>> {
     unsafe {
-        match WaitForSingleObject(child.as_raw_handle() as *mut _, ms) {
-            WAIT_OBJECT_0 => {}
-            WAIT_TIMEOUT => return Ok(None),
-            _ => return Err(io::Error::last_os_error()),
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        let mut ret = Vec::new();
+        let mut entry: PROCESSENTRY32W = mem::zeroed();
+        entry.dwSize = mem::size_of::<PROCESSENTRY32W>() as DWORD;
+        let mut ok = Process32FirstW(snapshot, &mut entry);
+        while ok != 0 {
+            ret.push((entry.th32ProcessID, entry.th32ParentProcessID));
+            ok = Process32NextW(snapshot, &mut entry);
+        }
+        CloseHandle(snapshot);
+        Ok(ret)
+    }
+}
+
+pub fn exited(_status: &ExitStatus) -> bool {
+    true
+}
+
+pub fn signaled(_status: &ExitStatus) -> bool {
+    false
+}
+
+pub fn stopped(_status: &ExitStatus) -> bool {
+    false
+}
+
+pub fn continued(_status: &ExitStatus) -> bool {
+    false
+}
+
+pub fn stop_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+pub fn term_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+pub fn raw_wait_status(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+// Processes which die of an unhandled exception exit with its `NTSTATUS`
+// code, whose top two bits are set for errors, such as `0xC0000005` for an
+// access violation. Ordinary programs don't exit with such codes.
+pub fn crashed(status: &ExitStatus) -> bool {
+    status
+        .code()
+        .is_some_and(|code| (code as u32) >> 30 == 0b11)
+}
+
+// Returns the index of the first of `handles` which became signaled before
+// `dur` elapsed.
+//
+// The wait is restarted if it times out early, which the system timer is
+// allowed to do, so `None` is only returned once all of `dur` has elapsed.
+fn wait_objects(handles: &[HANDLE], dur: Duration) -> io::Result<Option<usize>> {
+    let start = Instant::now();
+    loop {
+        let left = dur.saturating_sub(start.elapsed());
+        // Rounded up so we don't wake up before `dur` has elapsed, and
+        // `INFINITE` for durations too large to represent.
+        let ms = left.as_nanos().div_ceil(1_000_000);
+        let ms = if ms > (DWORD::MAX as u128) {
+            DWORD::MAX
+        } else {
+            ms as DWORD
+        };
+        unsafe {
+            let n = handles.len() as DWORD;
+            match WaitForMultipleObjects(n, handles.as_ptr(), FALSE, ms) {
+                WAIT_TIMEOUT if start.elapsed() >= dur => return Ok(None),
+                WAIT_TIMEOUT => {}
+                // `WAIT_OBJECT_0` is zero, so this is the index of the handle
+                r if r < n => return Ok(Some(r as usize)),
+                _ => return Err(io::Error::last_os_error()),
+            }
         }
     }
-    child.try_wait()
 }
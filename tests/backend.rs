@@ -0,0 +1,72 @@
+//! Kept in its own test binary, as the backend is picked once per process.
+
+#![cfg(unix)]
+
+extern crate libc;
+extern crate wait_timeout;
+
+use std::env;
+use std::io;
+use std::mem;
+use std::process::Command;
+use std::ptr;
+use std::time::Duration;
+
+use wait_timeout::{ChildExt, Waiter, WaiterOptions};
+
+fn sigchld_handler() -> libc::sighandler_t {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        assert_eq!(libc::sigaction(libc::SIGCHLD, ptr::null(), &mut action), 0);
+        action.sa_sigaction
+    }
+}
+
+#[test]
+fn forced_sigchld_backend() {
+    env::set_var("RUST_WAIT_TIMEOUT_BACKEND", "sigchld");
+
+    let mut me = env::current_exe().unwrap();
+    me.pop();
+    if me.ends_with("deps") {
+        me.pop();
+    }
+    me.push("sleep");
+
+    let before = sigchld_handler();
+
+    // Nothing is set up to wait on a child which has already exited.
+    let mut child = Command::new(&me).arg("0").spawn().unwrap();
+    unsafe {
+        let mut info: libc::siginfo_t = mem::zeroed();
+        let flags = libc::WEXITED | libc::WNOWAIT;
+        assert_eq!(
+            libc::waitid(libc::P_PID, child.id() as libc::id_t, &mut info, flags),
+            0
+        );
+    }
+    let status = child.wait_timeout(Duration::from_secs(10)).unwrap();
+    assert!(status.unwrap().success());
+    assert!(sigchld_handler() == before);
+
+    // Nor is it for a running child when there's no time to wait.
+    let mut child = Command::new(&me).arg("1000000").spawn().unwrap();
+    assert_eq!(child.wait_timeout(Duration::ZERO).unwrap(), None);
+    assert!(sigchld_handler() == before);
+
+    assert_eq!(child.wait_timeout(Duration::from_millis(10)).unwrap(), None);
+    child.kill().unwrap();
+    let status = child
+        .wait_timeout(Duration::from_secs(10))
+        .unwrap()
+        .unwrap();
+    assert!(!status.success());
+
+    // The handler is installed even where pidfds would otherwise be used.
+    assert!(sigchld_handler() != before);
+
+    // Which is only ever installed for a single signal.
+    let err = Waiter::with_options(WaiterOptions::new().child_signal(libc::SIGUSR2)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    drop(Waiter::with_options(WaiterOptions::new().child_signal(libc::SIGCHLD)).unwrap());
+}
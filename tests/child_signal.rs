@@ -0,0 +1,38 @@
+//! Kept in its own test binary, as the signal handler must not have been
+//! installed yet when this runs.
+
+#![cfg(unix)]
+
+extern crate libc;
+extern crate wait_timeout;
+
+use std::env;
+use std::io;
+use std::process::Command;
+use std::time::Duration;
+
+use wait_timeout::{ChildExt, Waiter, WaiterOptions};
+
+#[test]
+fn invalid_child_signal() {
+    env::set_var("RUST_WAIT_TIMEOUT_BACKEND", "sigchld");
+
+    for &signal in &[libc::SIGKILL, libc::SIGSTOP, 0, 10_000] {
+        let err = Waiter::with_options(WaiterOptions::new().child_signal(signal))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    // Nothing was left half initialized by the failed attempts.
+    let mut me = env::current_exe().unwrap();
+    me.pop();
+    if me.ends_with("deps") {
+        me.pop();
+    }
+    me.push("sleep");
+    let mut child = Command::new(&me).arg("0").spawn().unwrap();
+    let status = child.wait_timeout(Duration::from_secs(10)).unwrap();
+    assert!(status.unwrap().success());
+    drop(Waiter::new().unwrap());
+}
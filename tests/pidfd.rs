@@ -0,0 +1,122 @@
+//! Kept in its own test binary, as nothing else may install a `SIGCHLD`
+//! handler in the process while this runs.
+
+#![cfg(target_os = "linux")]
+
+extern crate libc;
+extern crate wait_timeout;
+
+use std::env;
+use std::mem;
+use std::process::Command;
+use std::ptr;
+use std::thread;
+use std::time::Duration;
+
+use wait_timeout::{ChildExt, Waiter};
+
+fn sigchld_action() -> (libc::sighandler_t, libc::c_int) {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        assert_eq!(libc::sigaction(libc::SIGCHLD, ptr::null(), &mut action), 0);
+        (action.sa_sigaction, action.sa_flags)
+    }
+}
+
+fn pidfd_supported() -> bool {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0) };
+    if fd < 0 {
+        return false;
+    }
+    unsafe {
+        libc::close(fd as libc::c_int);
+    }
+    true
+}
+
+fn helper(name: &str) -> Command {
+    let mut me = env::current_exe().unwrap();
+    me.pop();
+    if me.ends_with("deps") {
+        me.pop();
+    }
+    me.push(name);
+    Command::new(me)
+}
+
+// The number of times the calling thread has blocked so far.
+fn voluntary_switches() -> libc::c_long {
+    unsafe {
+        let mut usage: libc::rusage = mem::zeroed();
+        assert_eq!(libc::getrusage(libc::RUSAGE_THREAD, &mut usage), 0);
+        usage.ru_nvcsw
+    }
+}
+
+#[test]
+fn no_global_handler() {
+    // skip on kernels without pidfd support
+    if !pidfd_supported() {
+        return;
+    }
+    let me = helper("sleep").get_program().to_owned();
+
+    let before = sigchld_action();
+    let mut child = Command::new(&me).arg("1000000").spawn().unwrap();
+    assert_eq!(child.wait_timeout(Duration::from_millis(10)).unwrap(), None);
+    child.kill().unwrap();
+    let status = child
+        .wait_timeout(Duration::from_secs(10))
+        .unwrap()
+        .unwrap();
+    assert!(!status.success());
+
+    let mut child = Command::new(&me).arg("0").spawn().unwrap();
+    let status = child
+        .wait_timeout(Duration::from_secs(10))
+        .unwrap()
+        .unwrap();
+    assert!(status.success());
+    assert!(sigchld_action() == before);
+
+    // Nor does a waiter need one to notice its children exiting.
+    let waiter = Waiter::new().unwrap();
+    let mut child = Command::new(&me).arg("0").spawn().unwrap();
+    waiter.watch(&child);
+    let exits = waiter.poll_all(Duration::from_secs(10)).unwrap();
+    assert_eq!(exits.len(), 1);
+    assert_eq!(exits[0].0, child.id());
+    assert!(child.wait().unwrap().success());
+    assert!(sigchld_action() == before);
+}
+
+#[test]
+fn unrelated_exits_dont_wake() {
+    if !pidfd_supported() {
+        return;
+    }
+
+    // a wait blocks once until its own child exits, however many other
+    // children exit in the meantime, though it may also block briefly on a
+    // contended lock or page fault
+    let mut child = helper("exit").arg("0").spawn().unwrap();
+    child
+        .wait_timeout(Duration::from_secs(10))
+        .unwrap()
+        .unwrap();
+    let churn = thread::spawn(|| {
+        for _ in 0..100 {
+            helper("exit").arg("0").status().unwrap();
+        }
+    });
+    let mut child = helper("sleep").arg("500").spawn().unwrap();
+    let before = voluntary_switches();
+    let status = child
+        .wait_timeout(Duration::from_secs(10))
+        .unwrap()
+        .unwrap();
+    let switches = voluntary_switches() - before;
+    assert!(status.success());
+    churn.join().unwrap();
+    assert!(switches < 10, "blocked {} times", switches);
+}
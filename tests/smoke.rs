@@ -1,10 +1,15 @@
+#[cfg(unix)]
+extern crate libc;
 extern crate wait_timeout;
 
 use std::env;
-use std::process::{Child, Command, Stdio};
+use std::process::{Child, Command, ExitCode, Stdio};
+use std::thread;
 use std::time::{Duration, Instant};
 
-use wait_timeout::ChildExt;
+use wait_timeout::{
+    ChildExt, ExitStatusExt, Interrupt, OutputExt, Reaped, TryWaitResult, WaitResult,
+};
 
 macro_rules! t {
     ($e:expr) => {
@@ -15,6 +20,16 @@ macro_rules! t {
     };
 }
 
+fn helper(name: &str) -> Command {
+    let mut me = env::current_exe().unwrap();
+    me.pop();
+    if me.ends_with("deps") {
+        me.pop();
+    }
+    me.push(name);
+    Command::new(me)
+}
+
 fn sleeper(ms: u32) -> Child {
     let mut me = env::current_exe().unwrap();
     me.pop();
@@ -65,6 +80,88 @@ fn smoke_success() {
     assert!(start.elapsed() < Duration::from_millis(500));
 }
 
+#[test]
+fn smoke_checked() {
+    let mut child = sleeper(1_000_000);
+    let result = t!(child.wait_timeout_checked(Duration::from_millis(10)));
+    assert_eq!(result, WaitResult::TimedOut);
+    assert_eq!(result.into_option(), None);
+    t!(child.kill());
+
+    let result = t!(child.wait_timeout_checked(Duration::from_secs(10)));
+    match result {
+        WaitResult::Exited(status) => assert!(!status.success()),
+        WaitResult::TimedOut => panic!("timed out"),
+    }
+    assert_eq!(Option::from(result), Some(t!(child.wait())));
+}
+
+#[test]
+fn smoke_result() {
+    use wait_timeout::WaitTimeoutResult;
+
+    let mut child = sleeper(1_000_000);
+    match child.wait_timeout_result(Duration::from_millis(10)) {
+        WaitTimeoutResult::TimedOut => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    t!(child.kill());
+    match child.wait_timeout_result(Duration::from_secs(10)) {
+        WaitTimeoutResult::Exited(status) => assert!(!status.success()),
+        result => panic!("unexpected {:?}", result),
+    }
+    let status = t!(child.wait_timeout_result(Duration::ZERO).into_result());
+    assert!(!status.unwrap().success());
+}
+
+#[test]
+fn smoke_retrying() {
+    let mut child = sleeper(1_000_000);
+    let start = Instant::now();
+    assert_eq!(
+        t!(child.wait_timeout_retrying(Duration::from_millis(100), 3)),
+        None
+    );
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    t!(child.kill());
+    let status = t!(child.wait_timeout_retrying(Duration::from_secs(10), 3));
+    assert!(!status.unwrap().success());
+}
+
+#[test]
+fn smoke_flow() {
+    use std::ops::ControlFlow;
+
+    let mut child = sleeper(1_000_000);
+    assert_eq!(
+        t!(child.wait_timeout_flow(Duration::from_millis(10))),
+        ControlFlow::Continue(())
+    );
+    t!(child.kill());
+    let start = Instant::now();
+    let status = loop {
+        if let ControlFlow::Break(status) = t!(child.wait_timeout_flow(Duration::from_millis(100)))
+        {
+            break status;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+    };
+    assert!(!status.success());
+}
+
+#[test]
+fn smoke_wait_twice() {
+    let mut child = exit(4);
+    let status = t!(child.wait_timeout(Duration::from_secs(10))).unwrap();
+    assert_eq!(status.code(), Some(4));
+    assert_eq!(
+        t!(child.wait_timeout(Duration::from_secs(10))),
+        Some(status)
+    );
+    assert_eq!(t!(child.wait_timeout(Duration::ZERO)), Some(status));
+    assert_eq!(t!(child.wait()), status);
+}
+
 #[test]
 fn smoke_timeout() {
     let mut child = sleeper(1_000_000);
@@ -103,3 +200,1411 @@ fn exit_codes() {
         assert_eq!(status.code(), Some(259));
     }
 }
+
+#[test]
+fn secs_f64_invalid_is_zero() {
+    let mut child = sleeper(1_000);
+    assert_eq!(t!(child.wait_timeout_secs_f64(-1.0)), None);
+    assert_eq!(t!(child.wait_timeout_secs_f64(f64::NAN)), None);
+
+    t!(child.kill());
+    t!(child.wait());
+
+    let mut child = sleeper(0);
+    let status = t!(child.wait_timeout_secs_f64(f64::INFINITY)).unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn millis() {
+    let mut child = sleeper(0);
+    let status = t!(child.wait_timeout_millis(1_000)).unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn kill_after_fires() {
+    let mut child = sleeper(1_000_000);
+    let start = Instant::now();
+    {
+        let guard = t!(child.kill_after(Duration::from_millis(100)));
+        assert!(guard.id() > 0);
+        thread::sleep(Duration::from_millis(300));
+    }
+    let status = t!(child.wait());
+    assert!(!status.success());
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn kill_after_cancelled() {
+    let mut child = sleeper(200);
+    drop(t!(child.kill_after(Duration::from_millis(50))));
+    thread::sleep(Duration::from_millis(100));
+    let status = t!(child.wait());
+    assert!(status.success());
+}
+
+#[test]
+fn kill_after_wait() {
+    let mut child = sleeper(1_000_000);
+    let start = Instant::now();
+    let mut guard = t!(child.kill_after(Duration::from_millis(200)));
+    assert!(t!(guard.wait_timeout(Duration::from_millis(10))).is_none());
+    let status = t!(guard.wait());
+    assert!(!status.success());
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn kill_after_wait_exits() {
+    let mut child = sleeper(50);
+    let mut guard = t!(child.kill_after(Duration::from_secs(10)));
+    let status = t!(guard.wait_timeout(Duration::from_secs(10))).unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn kill_after_exited() {
+    let mut child = exit(0);
+    t!(child.wait());
+    let mut guard = t!(child.kill_after(Duration::from_millis(0)));
+    assert!(t!(guard.wait()).success());
+}
+
+#[cfg(windows)]
+#[test]
+fn wait_handle() {
+    use std::os::windows::prelude::*;
+
+    let mut child = exit(3);
+    let status = t!(wait_timeout::wait_handle_timeout(
+        child.as_raw_handle(),
+        Duration::from_secs(1)
+    ))
+    .unwrap();
+    assert_eq!(status.code(), Some(3));
+    t!(child.wait());
+}
+
+#[cfg(windows)]
+#[test]
+fn job_kill() {
+    use wait_timeout::CommandExt;
+
+    let (mut child, job) = t!(helper("sleep").arg("1000000").spawn_in_job());
+    assert_eq!(t!(child.wait_timeout_ms(0)), None);
+    t!(job.kill());
+    let status = t!(child.wait());
+    assert!(!status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn kill_process_group() {
+    use wait_timeout::CommandExt;
+
+    let mut child = t!(helper("sleep")
+        .arg("1000000")
+        .process_group_leader()
+        .spawn());
+    assert_eq!(t!(child.wait_timeout_ms(0)), None);
+    t!(child.kill_process_group());
+    let status = t!(child.wait());
+    assert!(!status.success());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn kill_process_group_leader_exited() {
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use wait_timeout::CommandExt;
+
+    // the shell leads the group and exits straight away, leaving its
+    // background job running in the group
+    let mut child = t!(Command::new("sh")
+        .arg("-c")
+        .arg("sleep 1000 & echo $!")
+        .stdout(Stdio::piped())
+        .process_group_leader()
+        .spawn());
+    let mut line = String::new();
+    t!(BufReader::new(child.stdout.take().unwrap()).read_line(&mut line));
+    let grandchild = line.trim().to_string();
+    assert!(t!(child.wait()).success());
+
+    t!(child.kill_process_group());
+
+    // the grandchild is either gone already or left as a zombie
+    let start = Instant::now();
+    loop {
+        match fs::read_to_string(format!("/proc/{}/stat", grandchild)) {
+            Ok(ref stat) if !stat.contains(") Z") => {}
+            _ => break,
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // once the group is empty killing it is still fine
+    t!(child.kill_process_group());
+}
+
+#[test]
+fn reaped_now_then_cached() {
+    let mut child = exit(0);
+    let (status, reaped) = t!(child.wait_timeout_reaped(Duration::from_secs(1))).unwrap();
+    assert!(status.success());
+    assert_eq!(reaped, Reaped::Now);
+
+    let (status, reaped) = t!(child.wait_timeout_reaped(Duration::from_secs(1))).unwrap();
+    assert!(status.success());
+
+    // processes aren't reaped on windows, so every status is fresh there
+    if cfg!(unix) {
+        assert_eq!(reaped, Reaped::Cached);
+    }
+}
+
+#[test]
+fn status_predicates() {
+    let mut child = exit(1);
+    let status = t!(child.wait_timeout_ms(1_000)).unwrap();
+    assert!(status.exited());
+    assert!(!status.signaled());
+    assert!(!status.stopped());
+    assert!(!status.continued());
+    assert_eq!(status.stop_signal(), None);
+    assert_eq!(status.code_or(-1), 1);
+    assert!(status.same_outcome(&status));
+    assert!(status.is_success_code(&[0, 1]));
+    assert!(!status.is_success_code(&[0]));
+    assert!(!status.is_success_code(&[]));
+    assert_eq!(status.signal_number(), None);
+    assert_eq!(status.shell_code(), 1);
+    assert_eq!(status.to_exit_code(), ExitCode::from(1));
+    assert!(!status.crashed());
+    if cfg!(unix) {
+        assert_eq!(status.raw_wait_status(), Some(1 << 8));
+    } else {
+        assert_eq!(status.raw_wait_status(), None);
+    }
+
+    let mut child = sleeper(1_000_000);
+    t!(child.kill());
+    let status = t!(child.wait());
+    assert_eq!(status.exited(), cfg!(windows));
+    assert_eq!(status.signaled(), cfg!(unix));
+
+    if cfg!(unix) {
+        // killed with SIGKILL
+        assert_eq!(status.signal_number(), Some(9));
+        assert_eq!(status.signal_or(-1), 9);
+        assert_eq!(status.code_or(-1), -1);
+        assert!(!status.is_success_code(&[-1, 9, 137]));
+        assert_eq!(status.shell_code(), 137);
+        assert!(!status.crashed());
+        assert_eq!(status.to_exit_code(), ExitCode::from(137));
+    } else {
+        assert_eq!(status.signal_number(), None);
+        assert_eq!(status.signal_or(-1), -1);
+        assert_eq!(status.shell_code(), 1);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn status_predicates_raw() {
+    use std::process::ExitStatus;
+
+    // not imported as its methods clash with `wait_timeout::ExitStatusExt`
+    fn from_raw(raw: i32) -> ExitStatus {
+        std::os::unix::process::ExitStatusExt::from_raw(raw)
+    }
+
+    // stopped by SIGTSTP
+    let status = from_raw(0x147f);
+    assert!(status.stopped());
+    assert!(!status.exited());
+    assert_eq!(status.stop_signal(), Some(20));
+
+    assert_eq!(status.raw_wait_status(), Some(0x147f));
+    assert!(!status.crashed());
+
+    // SIGSEGV, with a core dumped
+    let status = from_raw(0x8b);
+    assert!(status.crashed());
+    assert!(status.same_outcome(&from_raw(0xb)));
+    assert_ne!(status, from_raw(0xb));
+    assert!(!status.same_outcome(&from_raw(0x86)));
+    assert!(!status.same_outcome(&from_raw(0xb00)));
+
+    let status = from_raw(0xffff);
+    assert!(status.continued());
+    assert!(!status.stopped());
+    assert_eq!(status.stop_signal(), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn wait_timeout_with_pending() {
+    let mut child = t!(Command::new("sh")
+        .arg("-c")
+        .arg("echo hello; echo world >&2; sleep 5")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn());
+    let start = Instant::now();
+    let mut pending = (None, 0);
+    while pending.1 < 12 && start.elapsed() < Duration::from_secs(4) {
+        pending = t!(child.wait_timeout_with_pending(Duration::from_millis(100)));
+        assert_eq!(pending.0, None);
+    }
+    assert_eq!(pending.1, 12);
+    t!(child.kill());
+    t!(child.wait());
+
+    let mut child = exit(0);
+    let (status, pending) = t!(child.wait_timeout_with_pending(Duration::from_secs(10)));
+    assert!(status.unwrap().success());
+    assert_eq!(pending, 0);
+}
+
+#[test]
+fn interrupt_wakes_wait() {
+    let mut child = sleeper(1_000_000);
+    let interrupt = t!(Interrupt::new());
+    let interrupt2 = interrupt.clone();
+    let t = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        interrupt2.interrupt();
+    });
+    let start = Instant::now();
+    let status = t!(child.wait_timeout_interruptible(Duration::from_secs(100), &interrupt));
+    assert_eq!(status, None);
+    assert!(interrupt.is_interrupted());
+    assert!(start.elapsed() < Duration::from_secs(50));
+    t.join().unwrap();
+
+    t!(child.kill());
+    t!(child.wait());
+}
+
+#[test]
+fn interrupt_unset() {
+    let mut child = sleeper(0);
+    let interrupt = t!(Interrupt::new());
+    let status = t!(child.wait_timeout_interruptible(Duration::from_secs(1), &interrupt));
+    assert!(status.unwrap().success());
+    assert!(!interrupt.is_interrupted());
+}
+
+#[test]
+fn try_wait_deadline() {
+    let mut child = sleeper(1_000_000);
+    let later = Instant::now() + Duration::from_secs(100);
+    assert_eq!(t!(child.try_wait_deadline(later)), TryWaitResult::Running);
+    assert_eq!(
+        t!(child.try_wait_deadline(Instant::now())),
+        TryWaitResult::Overdue
+    );
+
+    t!(child.kill());
+    t!(child.wait());
+    match t!(child.try_wait_deadline(Instant::now())) {
+        TryWaitResult::Exited(status) => assert!(!status.success()),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter() {
+    let waiter = t!(wait_timeout::Waiter::new_with_capacity(16));
+    let mut child = sleeper(1_000_000);
+    assert_eq!(
+        t!(waiter.wait_timeout(&mut child, Duration::from_millis(10))),
+        None
+    );
+    t!(child.kill());
+    t!(child.wait());
+
+    let mut child = exit(2);
+    let status = t!(waiter.wait_timeout(&mut child, Duration::from_secs(1))).unwrap();
+    assert_eq!(status.code(), Some(2));
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_shared() {
+    use std::sync::Arc;
+
+    let waiter = Arc::new(t!(wait_timeout::Waiter::new()));
+    let threads = (0..16)
+        .map(|i| {
+            let waiter = waiter.clone();
+            thread::spawn(move || {
+                let mut child = exit(i);
+                let status = t!(waiter.wait_timeout(&mut child, Duration::from_secs(10)));
+                assert_eq!(status.unwrap().code(), Some(i as i32));
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+fn timestamped() {
+    let start = Instant::now();
+    let mut child = sleeper(100);
+    let (status, at) = t!(child.wait_timeout_timestamped(Duration::from_secs(10))).unwrap();
+    assert!(status.success());
+    assert!(at >= start + Duration::from_millis(100));
+    assert!(at <= Instant::now());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn kill_tree() {
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+
+    // spawn a shell which spawns a grandchild and reports its pid
+    let mut child = t!(Command::new("sh")
+        .arg("-c")
+        .arg("sleep 1000 & echo $!; wait")
+        .stdout(Stdio::piped())
+        .spawn());
+    let mut line = String::new();
+    t!(BufReader::new(child.stdout.take().unwrap()).read_line(&mut line));
+    let grandchild = line.trim().to_string();
+
+    t!(wait_timeout::kill_tree(&mut child));
+    let status = t!(child.wait());
+    assert!(!status.success());
+
+    // the grandchild is either gone already or left as a zombie
+    let start = Instant::now();
+    loop {
+        match fs::read_to_string(format!("/proc/{}/stat", grandchild)) {
+            Ok(ref stat) if !stat.contains(") Z") => {}
+            _ => break,
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn kill_tree_exited() {
+    let mut child = exit(0);
+    t!(child.wait());
+    t!(wait_timeout::kill_tree(&mut child));
+    assert!(t!(child.wait()).success());
+}
+
+#[test]
+fn wait_with_output() {
+    use wait_timeout::CommandExt;
+
+    let output = t!(helper("output")
+        .arg("out")
+        .arg("err")
+        .wait_with_output(Duration::from_secs(10)));
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"out");
+    assert_eq!(output.stderr, b"err");
+    assert_eq!(output.total_len(), 6);
+    assert!(!output.is_empty());
+
+    let mut merged = t!(helper("exit")
+        .arg("3")
+        .wait_with_output(Duration::from_secs(10)));
+    assert!(merged.is_empty());
+    assert_eq!(merged.status.code(), Some(3));
+    merged.extend_stdout(b"<");
+    merged.extend_stderr(b"<");
+    merged.merge(output);
+    assert_eq!(merged.stdout, b"<out");
+    assert_eq!(merged.stderr, b"<err");
+    assert!(merged.status.success());
+
+    let start = Instant::now();
+    let err = helper("sleep")
+        .arg("1000000")
+        .wait_with_output(Duration::from_millis(100))
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(50));
+}
+
+#[test]
+fn wait_with_output_timed() {
+    use wait_timeout::CommandExt;
+
+    let start = Instant::now();
+    let (output, elapsed) = t!(helper("sleep")
+        .arg("100")
+        .wait_with_output_timed(Duration::from_secs(10)));
+    assert!(output.status.success());
+    assert!(elapsed >= Duration::from_millis(100));
+    assert!(elapsed <= start.elapsed());
+
+    let err = helper("sleep")
+        .arg("1000000")
+        .wait_with_output_timed(Duration::from_millis(100))
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[cfg(unix)]
+#[test]
+fn wait_with_output_descendant_holds_pipes() {
+    use wait_timeout::CommandExt;
+
+    // the shell exits right away, but the backgrounded sleep keeps both of
+    // its pipes open
+    let start = Instant::now();
+    let err = Command::new("sh")
+        .arg("-c")
+        .arg("echo out; sleep 5 &")
+        .wait_with_output(Duration::from_millis(200))
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[cfg(unix)]
+#[test]
+fn wait_with_output_stdout_eof_grace() {
+    use wait_timeout::{CommandExt, OutputOptions};
+
+    // the backgrounded sleep keeps stderr open after the shell exits
+    let output = t!(Command::new("sh")
+        .arg("-c")
+        .arg("echo out; sleep 5 >/dev/null &")
+        .wait_with_output_opts(
+            Duration::from_secs(4),
+            OutputOptions::new().stdout_eof_grace(Duration::from_millis(100)),
+        ));
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"out\n");
+}
+
+#[test]
+fn wait_with_output_capacity() {
+    use wait_timeout::{CommandExt, OutputOptions};
+
+    let output = t!(helper("output")
+        .arg("out")
+        .arg("err")
+        .wait_with_output_opts(
+            Duration::from_secs(10),
+            OutputOptions::new()
+                .stdout_capacity(4096)
+                .stderr_capacity(1),
+        ));
+    assert_eq!(output.stdout, b"out");
+    assert_eq!(output.stderr, b"err");
+    assert!(output.stdout.capacity() >= 4096);
+}
+
+#[cfg(unix)]
+#[test]
+fn wait_with_output_idle_timeout() {
+    use wait_timeout::{CommandExt, OutputOptions};
+
+    // steady output keeps the child alive past the idle timeout
+    let output = t!(Command::new("sh")
+        .arg("-c")
+        .arg("for i in 1 2 3 4 5 6 7 8 9 10; do echo $i; sleep 0.1; done")
+        .wait_with_output_opts(
+            Duration::from_secs(100),
+            OutputOptions::new().idle_timeout(Duration::from_millis(500)),
+        ));
+    assert!(output.status.success());
+    assert_eq!(output.stdout.len(), 21);
+
+    let start = Instant::now();
+    let err = helper("sleep")
+        .arg("1000000")
+        .wait_with_output_opts(
+            Duration::from_secs(100),
+            OutputOptions::new().idle_timeout(Duration::from_millis(100)),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(50));
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_try_clone() {
+    let waiter = t!(wait_timeout::Waiter::new());
+    let mut child = exit(5);
+    waiter.watch(&child);
+
+    let clone = t!(waiter.try_clone());
+    let t = thread::spawn(move || {
+        let mut child = exit(6);
+        let status = t!(clone.wait_timeout(&mut child, Duration::from_secs(10)));
+        assert_eq!(status.unwrap().code(), Some(6));
+        // watched children aren't shared with the clone
+        assert!(t!(clone.drain_exits()).is_empty());
+    });
+    t.join().unwrap();
+
+    let exits = t!(waiter.poll_all(Duration::from_secs(10)));
+    assert_eq!(exits.len(), 1);
+    assert_eq!(exits[0].0, child.id());
+    assert_eq!(t!(child.wait()).code(), Some(5));
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_drain_exits() {
+    use std::os::unix::prelude::*;
+
+    let waiter = t!(wait_timeout::Waiter::new());
+    let mut child = exit(3);
+    waiter.watch(&child);
+
+    // the waiter's descriptor becomes readable once the child exits
+    let mut fd = libc::pollfd {
+        fd: waiter.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let start = Instant::now();
+    let exits = loop {
+        let exits = t!(waiter.drain_exits());
+        if !exits.is_empty() {
+            break exits;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+        unsafe {
+            libc::poll(&mut fd, 1, 100);
+        }
+    };
+    assert_eq!(exits.len(), 1);
+    assert_eq!(exits[0].0, child.id());
+    assert_eq!(exits[0].1.code(), Some(3));
+    assert!(t!(waiter.drain_exits()).is_empty());
+
+    // the child wasn't reaped, so std still collects its status
+    assert_eq!(t!(child.wait()).code(), Some(3));
+}
+
+#[test]
+fn ms_max() {
+    // neither wraps around to an immediate timeout
+    let mut child = sleeper(100);
+    let status = t!(child.wait_timeout_ms(u32::MAX)).unwrap();
+    assert!(status.success());
+
+    let mut child = sleeper(100);
+    let status = t!(child.wait_timeout_millis(u64::MAX)).unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn wait_with_input_output() {
+    use wait_timeout::CommandExt;
+
+    // large enough to fill the pipes in both directions
+    let input = vec![b'x'; 1 << 20];
+    let output = t!(helper("cat").wait_with_input_output(&input, Duration::from_secs(10)));
+    assert!(output.status.success());
+    assert!(output.stdout == input);
+
+    // the child exits without reading any of it
+    let output = t!(helper("exit")
+        .arg("0")
+        .wait_with_input_output(&input, Duration::from_secs(10)));
+    assert!(output.status.success());
+    assert!(output.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_spin() {
+    let waiter = t!(wait_timeout::Waiter::with_options(
+        wait_timeout::WaiterOptions::new().spin(Duration::from_millis(10))
+    ));
+    let mut child = exit(4);
+    let status = t!(waiter.wait_timeout(&mut child, Duration::from_secs(10))).unwrap();
+    assert_eq!(status.code(), Some(4));
+
+    let mut child = sleeper(1_000_000);
+    let start = Instant::now();
+    assert_eq!(
+        t!(waiter.wait_timeout(&mut child, Duration::from_millis(50))),
+        None
+    );
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    // a timeout within the spin is done entirely by spinning
+    let start = Instant::now();
+    assert_eq!(
+        t!(waiter.wait_timeout(&mut child, Duration::from_millis(1))),
+        None
+    );
+    assert!(start.elapsed() >= Duration::from_millis(1));
+    t!(child.kill());
+    t!(child.wait());
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_record_exit() {
+    use std::sync::Arc;
+
+    // an external reaper collects the child before we wait on it
+    let waiter = Arc::new(t!(wait_timeout::Waiter::new()));
+    let mut child = exit(5);
+    let mut raw = 0;
+    let pid = child.id() as libc::pid_t;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut raw, 0) }, pid);
+    waiter.record_exit(child.id(), raw);
+    let (status, reaped) = t!(child.wait_timeout_reaped(Duration::from_secs(10))).unwrap();
+    assert_eq!(status.code(), Some(5));
+    assert_eq!(reaped, Reaped::Cached);
+
+    // or while we're waiting on it
+    let mut child = sleeper(100);
+    let pid = child.id();
+    let waiter2 = waiter.clone();
+    let reaper = thread::spawn(move || {
+        let mut raw = 0;
+        let pid = pid as libc::pid_t;
+        if unsafe { libc::waitpid(pid, &mut raw, 0) } == pid {
+            waiter2.record_exit(pid as u32, raw);
+        }
+    });
+    let status = t!(child.wait_timeout(Duration::from_secs(10))).unwrap();
+    assert!(status.success());
+    reaper.join().unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn wait_pidfd() {
+    fn pidfd_open(pid: u32) -> libc::c_int {
+        unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) as libc::c_int }
+    }
+
+    // skip on kernels without pidfd support
+    let me = pidfd_open(std::process::id());
+    if me < 0 {
+        return;
+    }
+
+    let mut child = exit(6);
+    let pidfd = pidfd_open(child.id());
+    let status = t!(wait_timeout::wait_pidfd_timeout(
+        pidfd,
+        Duration::from_secs(10)
+    ))
+    .unwrap();
+    assert_eq!(status.code(), Some(6));
+    // already reaped through the pidfd
+    assert!(child.try_wait().is_err());
+
+    let mut child = sleeper(1_000_000);
+    let pidfd2 = pidfd_open(child.id());
+    assert_eq!(
+        t!(wait_timeout::wait_pidfd_timeout(
+            pidfd2,
+            Duration::from_millis(10)
+        )),
+        None
+    );
+    t!(child.kill());
+    t!(child.wait());
+    unsafe {
+        libc::close(me);
+        libc::close(pidfd);
+        libc::close(pidfd2);
+    }
+}
+
+#[test]
+fn wait_with_output_keep_stdio() {
+    use wait_timeout::{CommandExt, OutputOptions};
+
+    let output = t!(helper("output")
+        .arg("out")
+        .arg("err")
+        .stderr(Stdio::null())
+        .wait_with_output_opts(
+            Duration::from_secs(10),
+            OutputOptions::new().keep_stderr(true),
+        ));
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"out");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn wait_with_output_interruptible() {
+    use wait_timeout::{Collected, CommandExt};
+
+    let interrupt = t!(Interrupt::new());
+    let interrupt2 = interrupt.clone();
+    let t = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        interrupt2.interrupt();
+    });
+    let collected = t!(helper("sleep")
+        .arg("1000000")
+        .wait_with_output_interruptible(Duration::from_secs(100), &interrupt));
+    t.join().unwrap();
+    match collected {
+        Collected::Interrupted { mut child, .. } => {
+            // still running
+            assert_eq!(t!(child.try_wait()), None);
+            t!(child.kill());
+            t!(child.wait());
+        }
+        Collected::Exited(output) => panic!("unexpected exit {:?}", output),
+    }
+
+    let collected = t!(helper("output")
+        .arg("out")
+        .arg("err")
+        .wait_with_output_interruptible(Duration::from_secs(10), &t!(Interrupt::new())));
+    match collected {
+        Collected::Exited(output) => assert_eq!(output.stdout, b"out"),
+        Collected::Interrupted { .. } => panic!("unexpected interrupt"),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_drain_exits_zombies() {
+    let mut children = (0..4).map(exit).collect::<Vec<_>>();
+    for child in children.iter() {
+        // block until the child is a zombie, without reaping it
+        unsafe {
+            let mut info: libc::siginfo_t = std::mem::zeroed();
+            let flags = libc::WEXITED | libc::WNOWAIT;
+            assert_eq!(
+                libc::waitid(libc::P_PID, child.id() as libc::id_t, &mut info, flags),
+                0
+            );
+        }
+    }
+
+    let waiter = t!(wait_timeout::Waiter::new());
+    for child in children.iter() {
+        waiter.watch(child);
+    }
+    let mut exits = t!(waiter.drain_exits());
+    exits.sort_by_key(|&(_, status)| status.code());
+    assert_eq!(exits.len(), 4);
+    for (i, &(pid, status)) in exits.iter().enumerate() {
+        assert_eq!(pid, children[i].id());
+        assert_eq!(status.code(), Some(i as i32));
+    }
+    for child in children.iter_mut() {
+        t!(child.wait());
+    }
+}
+
+#[test]
+fn retry_timeout() {
+    let output = t!(wait_timeout::retry_timeout(
+        helper("output").arg("out").arg("err"),
+        3,
+        Duration::from_secs(10)
+    ));
+    assert_eq!(output.stdout, b"out");
+
+    // every attempt times out, and together they stay within the budget
+    let start = Instant::now();
+    let err = wait_timeout::retry_timeout(
+        helper("sleep").arg("1000000"),
+        3,
+        Duration::from_millis(300),
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(50));
+}
+
+#[test]
+fn heartbeat() {
+    let mut beats = 0;
+    let mut child = sleeper(200);
+    let status = t!(child.wait_timeout_with_heartbeat(
+        Duration::from_secs(10),
+        Duration::from_millis(10),
+        || {
+            beats += 1;
+            true
+        }
+    ));
+    assert!(status.unwrap().success());
+    assert!(beats > 0);
+
+    // abandoned by the callback
+    let mut child = sleeper(1_000_000);
+    let start = Instant::now();
+    let status = t!(child.wait_timeout_with_heartbeat(
+        Duration::from_secs(100),
+        Duration::from_millis(10),
+        || false
+    ));
+    assert_eq!(status, None);
+    assert!(start.elapsed() < Duration::from_secs(50));
+    t!(child.kill());
+    t!(child.wait());
+}
+
+#[cfg(unix)]
+#[test]
+fn reaped_elsewhere() {
+    let mut child = exit(0);
+    let pid = child.id() as libc::pid_t;
+    let mut raw = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut raw, 0) }, pid);
+
+    let err = child.wait_timeout(Duration::from_secs(1)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert!(err.to_string().contains(&pid.to_string()));
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_poll_all() {
+    let waiter = t!(wait_timeout::Waiter::new());
+    let mut child = sleeper(1_000_000);
+    waiter.watch(&child);
+    assert!(t!(waiter.poll_all(Duration::from_millis(10))).is_empty());
+    t!(child.kill());
+
+    let mut children = (0..3).map(exit).collect::<Vec<_>>();
+    for child in children.iter() {
+        waiter.watch(child);
+    }
+    let mut exits = Vec::new();
+    let start = Instant::now();
+    while exits.len() < 4 {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        exits.extend(t!(waiter.poll_all(Duration::from_secs(10))));
+    }
+    assert!(exits.iter().any(|&(pid, _)| pid == child.id()));
+    for child in children.iter() {
+        assert!(exits.iter().any(|&(pid, _)| pid == child.id()));
+    }
+
+    t!(child.wait());
+    for child in children.iter_mut() {
+        t!(child.wait());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_priority() {
+    let waiter = t!(wait_timeout::Waiter::new());
+    let mut children = (0..4).map(exit).collect::<Vec<_>>();
+    for (i, child) in children.iter().enumerate() {
+        waiter.watch_with_priority(child, i as i32 - 1);
+    }
+    // wait for all of them to exit so they're harvested together
+    for child in children.iter() {
+        let start = Instant::now();
+        while t!(waiter.peek(child)).is_none() {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+    let exits = t!(waiter.poll_all(Duration::from_secs(10)));
+    let pids = exits.iter().map(|&(pid, _)| pid).collect::<Vec<_>>();
+    let expected = children.iter().rev().map(|c| c.id()).collect::<Vec<_>>();
+    assert_eq!(pids, expected);
+
+    for child in children.iter_mut() {
+        t!(child.wait());
+    }
+}
+
+#[test]
+fn no_early_timeout() {
+    let mut child = sleeper(1_000_000);
+    for &us in [1, 500, 1_500, 10_250].iter() {
+        let dur = Duration::from_micros(us);
+        let start = Instant::now();
+        assert_eq!(t!(child.wait_timeout(dur)), None);
+        assert!(start.elapsed() >= dur);
+    }
+
+    t!(child.kill());
+    t!(child.wait());
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_peek() {
+    let waiter = t!(wait_timeout::Waiter::new());
+    let mut child = exit(3);
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = t!(waiter.peek(&child)) {
+            break status;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(1));
+    };
+    assert_eq!(status.code(), Some(3));
+    assert_eq!(t!(waiter.peek(&child)), Some(status));
+
+    // The child wasn't reaped, so it can still be waited on.
+    assert_eq!(t!(child.wait()), status);
+    let err = waiter.peek(&child).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[cfg(unix)]
+#[test]
+fn reused_pids() {
+    use wait_timeout::Waiter;
+
+    let waiter = t!(Waiter::new());
+    let mut a = sleeper(1_000_000);
+    assert_eq!(
+        t!(waiter.wait_timeout(&mut a, Duration::from_millis(10))),
+        None
+    );
+    t!(a.kill());
+    let a_status = t!(a.wait());
+
+    // Churn through children so that, with some luck, `a`'s pid is handed out
+    // again. Each must get its own status, and `a` must keep its own.
+    for round in 0..4 {
+        let mut children = (0..32).map(exit).collect::<Vec<_>>();
+        for (i, child) in children.iter_mut().enumerate() {
+            let status = if round % 2 == 0 {
+                t!(waiter.wait_timeout(child, Duration::from_secs(10)))
+            } else {
+                t!(child.wait_timeout(Duration::from_secs(10)))
+            };
+            assert_eq!(status.unwrap().code(), Some(i as i32));
+        }
+        assert_eq!(
+            t!(waiter.wait_timeout(&mut a, Duration::from_millis(10))),
+            Some(a_status)
+        );
+        assert_eq!(
+            t!(a.wait_timeout(Duration::from_millis(10))),
+            Some(a_status)
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_teardown() {
+    use wait_timeout::{Waiter, WaiterOptions};
+
+    // Waiters notified by the signal handler, as asking for a signal ensures,
+    // are limited to 64 live at once, so this fails if dropping a waiter which
+    // still watches a child and timed out on it leaks anything. The timeout
+    // isn't zero so that the wait really blocks and sets up its own
+    // notification, see the unit tests for the shared map of waits.
+    let mut options = WaiterOptions::new();
+    options.child_signal(libc::SIGCHLD);
+    let mut child = sleeper(1_000_000);
+    for _ in 0..200 {
+        let waiter = t!(Waiter::with_options(&options));
+        waiter.watch(&child);
+        assert_eq!(
+            t!(waiter.wait_timeout(&mut child, Duration::from_millis(1))),
+            None
+        );
+    }
+    t!(child.kill());
+    t!(child.wait());
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_remove() {
+    let waiter = t!(wait_timeout::Waiter::new());
+    let mut child = exit(0);
+    assert!(!waiter.contains(child.id()));
+    waiter.watch(&child);
+    assert!(waiter.contains(child.id()));
+    t!(waiter.remove(child.id()));
+    assert!(!waiter.contains(child.id()));
+    let err = waiter.remove(child.id()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+
+    // its exit is no longer reported
+    let start = Instant::now();
+    while t!(waiter.peek(&child)).is_none() {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(t!(waiter.poll_all(Duration::from_millis(100))).is_empty());
+    assert!(t!(child.wait()).success());
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_block_signals() {
+    use std::os::unix::thread::JoinHandleExt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use wait_timeout::{Waiter, WaiterOptions};
+
+    static DELIVERED: AtomicBool = AtomicBool::new(false);
+    extern "C" fn handler(_: libc::c_int) {
+        DELIVERED.store(true, Ordering::SeqCst);
+    }
+    unsafe {
+        libc::signal(libc::SIGUSR2, handler as *const () as libc::sighandler_t);
+    }
+
+    assert!(Waiter::with_options(WaiterOptions::new().block_signals(&[-1])).is_err());
+    let waiter = Arc::new(t!(Waiter::with_options(
+        WaiterOptions::new().block_signals(&[libc::SIGUSR2, libc::SIGCHLD])
+    )));
+    let waiter2 = waiter.clone();
+    let t = thread::spawn(move || {
+        let mut child = sleeper(1_000_000);
+        assert_eq!(
+            t!(waiter2.wait_timeout(&mut child, Duration::from_millis(500))),
+            None
+        );
+        t!(child.kill());
+        // child exits still end the wait
+        assert!(t!(waiter2.wait_timeout(&mut child, Duration::from_secs(10))).is_some());
+    });
+    thread::sleep(Duration::from_millis(100));
+    unsafe {
+        assert_eq!(libc::pthread_kill(t.as_pthread_t(), libc::SIGUSR2), 0);
+    }
+    thread::sleep(Duration::from_millis(100));
+    assert!(!DELIVERED.load(Ordering::SeqCst));
+    t.join().unwrap();
+    assert!(DELIVERED.load(Ordering::SeqCst));
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_around_blocking() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use wait_timeout::{Waiter, WaiterOptions};
+
+    let before = Arc::new(AtomicUsize::new(0));
+    let after = Arc::new(AtomicUsize::new(0));
+    let (before2, after2) = (before.clone(), after.clone());
+    let waiter = t!(Waiter::with_options(WaiterOptions::new().around_blocking(
+        move || {
+            before2.fetch_add(1, Ordering::SeqCst);
+        },
+        move || {
+            after2.fetch_add(1, Ordering::SeqCst);
+        },
+    )));
+
+    let mut child = exit(0);
+    let status = t!(waiter.wait_timeout(&mut child, Duration::from_secs(10)));
+    assert!(status.unwrap().success());
+    assert_eq!(before.load(Ordering::SeqCst), 1);
+    assert_eq!(after.load(Ordering::SeqCst), 1);
+
+    t!(waiter.poll_all(Duration::from_millis(1)));
+    assert_eq!(before.load(Ordering::SeqCst), 2);
+    assert_eq!(after.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn session_leader() {
+    use wait_timeout::CommandExt;
+
+    let mut cmd = helper("sleep");
+    cmd.arg("1000000").session_leader();
+    let mut child = t!(cmd.spawn());
+    let pid = child.id() as libc::pid_t;
+    assert_eq!(unsafe { libc::getsid(pid) }, pid);
+    assert_eq!(unsafe { libc::getpgid(pid) }, pid);
+    t!(child.kill_process_group());
+    let status = t!(child.wait_timeout(Duration::from_secs(10))).unwrap();
+    assert!(!status.success());
+
+    // Configuring it twice still starts a single new session.
+    cmd.session_leader();
+    let mut child = t!(cmd.spawn());
+    let pid = child.id() as libc::pid_t;
+    assert_eq!(unsafe { libc::getsid(pid) }, pid);
+    t!(child.kill());
+    t!(child.wait());
+}
+
+#[test]
+fn wait_with_output_to() {
+    use wait_timeout::CommandExt;
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let status = t!(helper("output").arg("out").arg("err").wait_with_output_to(
+        Duration::from_secs(10),
+        &mut stdout,
+        &mut stderr
+    ));
+    assert!(status.success());
+    assert_eq!(stdout, b"out");
+    assert_eq!(stderr, b"err");
+
+    let err = helper("sleep")
+        .arg("1000000")
+        .wait_with_output_to(Duration::from_millis(50), Vec::new(), Vec::new())
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[cfg(unix)]
+#[test]
+fn stdio_left_blocking() {
+    use std::os::unix::prelude::*;
+
+    let mut child = t!(helper("sleep")
+        .arg("1000000")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn());
+    assert_eq!(t!(child.wait_timeout(Duration::from_millis(10))), None);
+
+    // a plain `read` on the child's pipes must still block afterwards
+    let fds = [
+        child.stdout.as_ref().unwrap().as_raw_fd(),
+        child.stderr.as_ref().unwrap().as_raw_fd(),
+    ];
+    for &fd in fds.iter() {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        assert!(flags != -1);
+        assert_eq!(flags & libc::O_NONBLOCK, 0);
+    }
+
+    t!(child.kill());
+    t!(child.wait());
+}
+
+#[cfg(unix)]
+#[test]
+fn wait_or_readable() {
+    use std::io::{BufRead, BufReader, Write};
+    use wait_timeout::WaitOrRead;
+
+    let mut child = t!(Command::new("sh")
+        .arg("-c")
+        .arg("while read line; do echo $line; done")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn());
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let dur = Duration::from_secs(10);
+
+    assert_eq!(
+        t!(child.wait_or_readable(stdout.get_ref(), Duration::from_millis(10))),
+        WaitOrRead::TimedOut
+    );
+    for msg in ["ping\n", "pong\n"].iter() {
+        t!(child.stdin.as_mut().unwrap().write_all(msg.as_bytes()));
+        assert_eq!(
+            t!(child.wait_or_readable(stdout.get_ref(), dur)),
+            WaitOrRead::Readable
+        );
+        let mut line = String::new();
+        t!(stdout.read_line(&mut line));
+        assert_eq!(line, *msg);
+    }
+
+    drop(child.stdin.take());
+    let start = Instant::now();
+    let status = loop {
+        match t!(child.wait_or_readable(stdout.get_ref(), dur)) {
+            WaitOrRead::Exited(status) => break status,
+            // end of file on stdout may be seen before the exit
+            WaitOrRead::Readable => thread::sleep(Duration::from_millis(1)),
+            WaitOrRead::TimedOut => panic!("timed out"),
+        }
+        assert!(start.elapsed() < dur);
+    };
+    assert!(status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn wait_first_activity() {
+    use std::io::Write;
+    use wait_timeout::Activity;
+
+    let mut child = t!(Command::new("sh")
+        .arg("-c")
+        .arg("read line; echo $line >&2; sleep 5")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn());
+    assert_eq!(
+        t!(child.wait_first_activity(Duration::from_millis(10))),
+        Activity::TimedOut
+    );
+    t!(child.stdin.as_mut().unwrap().write_all(b"hello\n"));
+    assert_eq!(
+        t!(child.wait_first_activity(Duration::from_secs(10))),
+        Activity::Output
+    );
+    t!(child.kill());
+    t!(child.wait());
+
+    let mut child = exit(0);
+    match t!(child.wait_first_activity(Duration::from_secs(10))) {
+        Activity::Exited(status) => assert!(status.success()),
+        activity => panic!("unexpected {:?}", activity),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn poll_snapshot() {
+    let mut child = t!(Command::new("sh")
+        .arg("-c")
+        .arg("echo out; sleep 0.2; echo err >&2; sleep 0.2; exit 3")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn());
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let mut status = None;
+    let start = Instant::now();
+    while status.is_none() || child.stdout.is_some() || child.stderr.is_some() {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        let snapshot = t!(child.poll_snapshot(Duration::from_millis(100)));
+        assert_eq!(snapshot.is_running(), snapshot.status.is_none());
+        stdout.extend(snapshot.stdout);
+        stderr.extend(snapshot.stderr);
+        status = status.or(snapshot.status);
+    }
+    assert_eq!(stdout, b"out\n");
+    assert_eq!(stderr, b"err\n");
+    assert_eq!(status.unwrap().code(), Some(3));
+}
+
+#[cfg(unix)]
+#[test]
+fn waiter_max_pending() {
+    use std::sync::Arc;
+    use wait_timeout::{Waiter, WaiterOptions};
+
+    let waiter = Arc::new(t!(Waiter::with_options(
+        WaiterOptions::new().max_pending(1)
+    )));
+    let mut child = sleeper(1_000_000);
+    let waiter2 = waiter.clone();
+    let t = thread::spawn(move || {
+        let mut child = sleeper(1_000_000);
+        let ret = t!(waiter2.wait_timeout(&mut child, Duration::from_millis(500)));
+        t!(child.kill());
+        t!(child.wait());
+        ret
+    });
+
+    let start = Instant::now();
+    while waiter.pending_count() == 0 {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::yield_now();
+    }
+    assert!(waiter.wait_timeout(&mut child, Duration::ZERO).is_err());
+    assert_eq!(t.join().unwrap(), None);
+
+    assert_eq!(waiter.pending_count(), 0);
+    assert_eq!(t!(waiter.wait_timeout(&mut child, Duration::ZERO)), None);
+    t!(child.kill());
+    t!(child.wait());
+}
+
+#[test]
+fn wait_with_output_to_slow_sink() {
+    use std::io::{self, Write};
+    use wait_timeout::CommandExt;
+
+    // accepts a few bytes per call, and takes its time now and then
+    struct Trickle(Vec<u8>);
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = std::cmp::min(buf.len(), 7);
+            self.0.extend_from_slice(&buf[..n]);
+            if self.0.len() % 10_000 < 7 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut stdout = Trickle(Vec::new());
+    let big = "x".repeat(100_000);
+    let status = t!(helper("output").arg(&big).arg("").wait_with_output_to(
+        Duration::from_secs(100),
+        &mut stdout,
+        io::sink()
+    ));
+    assert!(status.success());
+    assert_eq!(stdout.0, big.as_bytes());
+}
+
+#[test]
+fn spawn_and_wait() {
+    use wait_timeout::CommandExt;
+
+    let (_, status) = t!(helper("exit")
+        .arg("7")
+        .spawn_and_wait(Duration::from_secs(10)));
+    assert_eq!(status.unwrap().code(), Some(7));
+
+    // the child is left running on a timeout
+    let (mut child, status) = t!(helper("sleep")
+        .arg("1000000")
+        .spawn_and_wait(Duration::from_millis(10)));
+    assert_eq!(status, None);
+    assert_eq!(t!(child.try_wait()), None);
+    t!(child.kill());
+    t!(child.wait());
+}
+
+#[test]
+fn huge_capacities() {
+    use wait_timeout::safe::{CommandExt, OutputOptions};
+
+    let err = helper("exit")
+        .arg("0")
+        .wait_with_output_opts(
+            Duration::from_secs(10),
+            OutputOptions::new().stdout_capacity(usize::MAX),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::OutOfMemory);
+
+    #[cfg(unix)]
+    {
+        let err = wait_timeout::safe::Waiter::new_with_capacity(usize::MAX)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::OutOfMemory);
+    }
+}
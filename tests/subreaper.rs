@@ -0,0 +1,56 @@
+//! Kept in its own test binary, as it makes the whole process a subreaper and
+//! reaps children which other tests would be waiting on.
+
+#![cfg(target_os = "linux")]
+
+extern crate libc;
+extern crate wait_timeout;
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use wait_timeout::Waiter;
+
+#[test]
+fn reap_orphans() {
+    assert_eq!(
+        unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) },
+        0
+    );
+    let waiter = Waiter::new().unwrap();
+
+    // the backgrounded sleep is reparented to us once the shell exits, and
+    // also holds on to stdout until it exits itself
+    let mut sh = Command::new("sh")
+        .arg("-c")
+        .arg("sleep 0.1 & echo $!")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let known = sh.id();
+    let mut out = String::new();
+    sh.stdout.take().unwrap().read_to_string(&mut out).unwrap();
+    let orphan = out.trim().parse::<u32>().unwrap();
+
+    // wait for the shell to exit without reaping it, so it's a zombie child
+    // which must be left alone as it's known
+    let start = Instant::now();
+    while waiter.peek(&sh).unwrap().is_none() {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut reaped = Vec::new();
+    while reaped.is_empty() {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        reaped = waiter.reap_orphans(|pid| pid == known).unwrap();
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(reaped.len(), 1);
+    assert_eq!(reaped[0].0, orphan);
+    assert!(reaped[0].1.success());
+
+    assert!(sh.wait().unwrap().success());
+}